@@ -0,0 +1,573 @@
+/** Network client for Broadlink RM devices
+ * Protocol from: https://github.com/mjg59/python-broadlink/blob/master/protocol.md
+ * Devices answer discovery broadcasts with their type and MAC address. Commands are then sent
+ * with an AES-128-CBC encrypted payload, using a session key obtained through authentication.
+ */
+use std::{
+    fs,
+    io::ErrorKind,
+    net::{IpAddr, Ipv4Addr, SocketAddr, UdpSocket},
+    path::PathBuf,
+    thread,
+    time::{Duration, Instant},
+};
+
+use aes::Aes128;
+use block_modes::{block_padding::NoPadding, BlockMode, Cbc};
+use clap::Parser;
+use hex_literal::hex;
+use serde::Deserialize;
+use thiserror::Error;
+
+use super::{ParseError, Recording};
+
+type Aes128Cbc = Cbc<Aes128, NoPadding>;
+
+/// Key used until authentication provides the session key
+pub(super) const DEFAULT_KEY: [u8; 16] = hex!("097628343fe99e23765c1513accf8b02");
+const IV: [u8; 16] = hex!("562e17996d093d28ddb3ba695a2e6f58");
+const MAGIC: [u8; 8] = hex!("5aa5aa555aa5aa55");
+
+pub const PORT: u16 = 80;
+
+pub(super) const DISCOVER_LEN: usize = 0x30;
+pub(super) const DISCOVER_RESPONSE_LEN: usize = 0x80;
+pub(super) const DISCOVER_COMMAND: u8 = 0x06;
+pub(super) const AUTH_COMMAND: u16 = 0x65;
+pub(super) const RM_COMMAND: u16 = 0x6a;
+
+pub(super) const SEND_DATA: u32 = 0x02;
+pub(super) const ENTER_LEARNING: u32 = 0x03;
+pub(super) const CHECK_DATA: u32 = 0x04;
+
+/// Errors returned by check_data while nothing has been learned (StorageError and ReadError in
+/// python-broadlink)
+pub(super) const STORAGE_ERROR: i16 = -5;
+const READ_ERROR: i16 = -10;
+
+/// RM4 devices prefix command payloads with their length
+const RM4_TYPES: &[u16] = &[
+    0x51da, 0x5209, 0x520b, 0x520c, 0x520d, 0x5211, 0x5212, 0x5213, 0x5216, 0x5218, 0x6026, 0x6070,
+    0x610e, 0x610f, 0x6184, 0x61a2, 0x62bc, 0x62be, 0x6364, 0x648d, 0x6539, 0x653a, 0x653c,
+];
+
+#[derive(Error, Debug)]
+pub enum ClientError {
+    #[error("network error: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("timed out waiting for the device")]
+    Timeout,
+    #[error("invalid packet: {0}")]
+    InvalidPacket(&'static str),
+    #[error("device returned error {0}")]
+    Device(i16),
+    #[error("invalid code received: {0}")]
+    Recording(#[from] ParseError),
+    #[error("no device found")]
+    NotFound,
+    #[error("invalid host address: {0}")]
+    InvalidHost(String),
+    #[error("invalid MAC address: {0}")]
+    InvalidMac(String),
+    #[error("invalid device configuration: {0}")]
+    Config(#[from] serde_json::Error),
+}
+
+pub(super) fn checksum(data: &[u8]) -> u16 {
+    data.iter()
+        .fold(0xbeafu32, |sum, &b| sum.wrapping_add(b as u32)) as u16
+}
+
+pub(super) fn encrypt(key: &[u8; 16], data: &[u8]) -> Vec<u8> {
+    let mut data = data.to_vec();
+    data.resize(data.len().div_ceil(16) * 16, 0);
+    Aes128Cbc::new_from_slices(key, &IV)
+        .unwrap()
+        .encrypt_vec(&data)
+}
+
+pub(super) fn decrypt(key: &[u8; 16], data: &[u8]) -> Result<Vec<u8>, ClientError> {
+    Aes128Cbc::new_from_slices(key, &IV)
+        .unwrap()
+        .decrypt_vec(data)
+        .map_err(|_| ClientError::InvalidPacket("payload isn't a whole number of blocks"))
+}
+
+/// Unencrypted header of command packets and their responses
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) struct Header {
+    pub error: i16,
+    pub devtype: u16,
+    pub command: u16,
+    pub count: u16,
+    pub mac: [u8; 6],
+    pub id: u32,
+}
+
+impl Header {
+    const LEN: usize = 0x38;
+
+    /// Builds a packet holding the payload, encrypted with the key
+    pub fn encode(&self, key: &[u8; 16], payload: &[u8]) -> Vec<u8> {
+        let mut packet = vec![0u8; Header::LEN];
+        packet[..0x08].copy_from_slice(&MAGIC);
+        packet[0x22..0x24].copy_from_slice(&self.error.to_le_bytes());
+        packet[0x24..0x26].copy_from_slice(&self.devtype.to_le_bytes());
+        packet[0x26..0x28].copy_from_slice(&self.command.to_le_bytes());
+        packet[0x28..0x2a].copy_from_slice(&self.count.to_le_bytes());
+        packet[0x2a..0x30].copy_from_slice(&reversed(self.mac));
+        packet[0x30..0x34].copy_from_slice(&self.id.to_le_bytes());
+        packet[0x34..0x36].copy_from_slice(&checksum(payload).to_le_bytes());
+
+        packet.extend(encrypt(key, payload));
+        let checksum = checksum(&packet);
+        packet[0x20..0x22].copy_from_slice(&checksum.to_le_bytes());
+        packet
+    }
+
+    /// Validates a packet, returning its header and decrypted payload
+    pub fn decode(packet: &[u8], key: &[u8; 16]) -> Result<(Header, Vec<u8>), ClientError> {
+        if packet.len() < Header::LEN || packet[..0x08] != MAGIC {
+            return Err(ClientError::InvalidPacket("not a command packet"));
+        }
+
+        let mut unsigned = packet.to_vec();
+        unsigned[0x20..0x22].fill(0);
+        if checksum(&unsigned) != u16::from_le_bytes([packet[0x20], packet[0x21]]) {
+            return Err(ClientError::InvalidPacket("invalid checksum"));
+        }
+
+        let u16_at = |i: usize| u16::from_le_bytes([packet[i], packet[i + 1]]);
+        let header = Header {
+            error: u16_at(0x22) as i16,
+            devtype: u16_at(0x24),
+            command: u16_at(0x26),
+            count: u16_at(0x28),
+            mac: reversed(packet[0x2a..0x30].try_into().unwrap()),
+            id: u32::from_le_bytes(packet[0x30..0x34].try_into().unwrap()),
+        };
+
+        // Errors come without a payload
+        if header.error != 0 {
+            return Ok((header, Vec::new()));
+        }
+
+        Ok((header, decrypt(key, &packet[Header::LEN..])?))
+    }
+}
+
+/// MAC addresses are sent in reverse order
+pub(super) fn reversed(mut mac: [u8; 6]) -> [u8; 6] {
+    mac.reverse();
+    mac
+}
+
+pub fn parse_mac(mac: &str) -> Result<[u8; 6], ClientError> {
+    let digits: String = mac.chars().filter(|c| !matches!(c, ':' | '-')).collect();
+    hex::decode(&digits)
+        .ok()
+        .and_then(|mac| mac.try_into().ok())
+        .ok_or_else(|| ClientError::InvalidMac(mac.into()))
+}
+
+pub(super) fn is_rm4(devtype: u16) -> bool {
+    RM4_TYPES.contains(&devtype)
+}
+
+/// Device found by discovery, or configured by hand
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Device {
+    pub addr: SocketAddr,
+    pub mac: [u8; 6],
+    pub devtype: u16,
+    pub name: String,
+}
+
+impl Device {
+    pub fn is_rm4(&self) -> bool {
+        is_rm4(self.devtype)
+    }
+}
+
+impl std::fmt::Display for Device {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} {} 0x{:04x} {}",
+            self.addr.ip(),
+            hex::encode(self.mac),
+            self.devtype,
+            self.name
+        )
+    }
+}
+
+pub(super) fn discovery_packet(local: SocketAddr) -> Vec<u8> {
+    // The time fields are left zeroed
+    let mut packet = vec![0u8; DISCOVER_LEN];
+    if let IpAddr::V4(ip) = local.ip() {
+        let mut octets = ip.octets();
+        octets.reverse();
+        packet[0x18..0x1c].copy_from_slice(&octets);
+    }
+    packet[0x1c..0x1e].copy_from_slice(&local.port().to_le_bytes());
+    packet[0x26] = DISCOVER_COMMAND;
+
+    let checksum = checksum(&packet);
+    packet[0x20..0x22].copy_from_slice(&checksum.to_le_bytes());
+    packet
+}
+
+pub(super) fn parse_discovery_response(
+    addr: SocketAddr,
+    packet: &[u8],
+) -> Result<Device, ClientError> {
+    if packet.len() < DISCOVER_RESPONSE_LEN {
+        return Err(ClientError::InvalidPacket(
+            "discovery response is too short",
+        ));
+    }
+
+    let name = packet[0x40..].split(|&b| b == 0).next().unwrap_or_default();
+    Ok(Device {
+        addr,
+        mac: reversed(packet[0x3a..0x40].try_into().unwrap()),
+        devtype: u16::from_le_bytes([packet[0x34], packet[0x35]]),
+        name: String::from_utf8_lossy(name).into(),
+    })
+}
+
+/// Local address the device can answer to, as routed towards it
+fn local_addr(target: SocketAddr, port: u16) -> SocketAddr {
+    let ip = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0))
+        .and_then(|probe| {
+            probe.set_broadcast(true)?;
+            probe.connect(target)?;
+            probe.local_addr()
+        })
+        .map_or(IpAddr::V4(Ipv4Addr::UNSPECIFIED), |addr| addr.ip());
+    SocketAddr::new(ip, port)
+}
+
+fn is_timeout(e: &std::io::Error) -> bool {
+    matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut)
+}
+
+/// Sends a discovery packet to the target, a broadcast address or a single device, and
+/// collects the answers until the timeout expires
+pub fn discover(target: SocketAddr, timeout: Duration) -> Result<Vec<Device>, ClientError> {
+    let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0))?;
+    socket.set_broadcast(true)?;
+
+    let local = local_addr(target, socket.local_addr()?.port());
+    socket.send_to(&discovery_packet(local), target)?;
+
+    let deadline = Instant::now() + timeout;
+    let mut devices: Vec<Device> = Vec::new();
+    let mut buf = [0u8; 1024];
+
+    while let Some(remaining) = deadline.checked_duration_since(Instant::now()) {
+        socket.set_read_timeout(Some(remaining.max(Duration::from_millis(1))))?;
+        let (len, addr) = match socket.recv_from(&mut buf) {
+            Ok(received) => received,
+            Err(e) if is_timeout(&e) => break,
+            Err(e) => return Err(e.into()),
+        };
+
+        // Our own broadcast, and anything else that isn't a device, is skipped
+        if let Ok(device) = parse_discovery_response(addr, &buf[..len]) {
+            if !devices.contains(&device) {
+                devices.push(device);
+            }
+        }
+    }
+
+    Ok(devices)
+}
+
+/// Device selection, from flags or a JSON configuration file
+#[derive(Clone, Parser, Debug)]
+pub struct DeviceOpts {
+    /// Device address, with an optional port. Devices are discovered on the local network
+    /// when missing.
+    #[clap(long)]
+    pub host: Option<String>,
+
+    /// Device MAC address, picking the device among the discovered ones
+    #[clap(long)]
+    pub mac: Option<String>,
+
+    /// JSON file holding the host and mac (e.g. {"host": "192.168.1.235"}), overridden by the
+    /// flags
+    #[clap(long)]
+    pub device_config: Option<PathBuf>,
+
+    /// Seconds to wait for devices to answer
+    #[clap(long, default_value = "3")]
+    pub timeout: u64,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct DeviceConfig {
+    host: Option<String>,
+    mac: Option<String>,
+}
+
+impl DeviceOpts {
+    /// Finds the selected device
+    pub fn device(&self) -> Result<Device, ClientError> {
+        let config: DeviceConfig = match &self.device_config {
+            Some(path) => serde_json::from_slice(&fs::read(path)?)?,
+            None => Default::default(),
+        };
+        let host = self.host.as_ref().or(config.host.as_ref());
+        let mac = self.mac.as_ref().or(config.mac.as_ref());
+
+        let target = match host {
+            Some(host) => parse_host(host)?,
+            None => SocketAddr::new(IpAddr::V4(Ipv4Addr::BROADCAST), PORT),
+        };
+        let mac = mac.map(|mac| parse_mac(mac)).transpose()?;
+
+        discover(target, self.timeout())?
+            .into_iter()
+            .find(|device| mac.is_none_or(|mac| device.mac == mac))
+            .ok_or(ClientError::NotFound)
+    }
+
+    pub fn timeout(&self) -> Duration {
+        Duration::from_secs(self.timeout)
+    }
+}
+
+fn parse_host(host: &str) -> Result<SocketAddr, ClientError> {
+    if let Ok(addr) = host.parse() {
+        return Ok(addr);
+    }
+
+    host.parse::<IpAddr>()
+        .map(|ip| SocketAddr::new(ip, PORT))
+        .map_err(|_| ClientError::InvalidHost(host.into()))
+}
+
+/// Authenticated connection to an RM device
+pub struct Client {
+    socket: UdpSocket,
+    device: Device,
+    key: [u8; 16],
+    id: u32,
+    count: u16,
+}
+
+impl Client {
+    /// Connects and authenticates to the device
+    pub fn connect(device: Device, timeout: Duration) -> Result<Self, ClientError> {
+        let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0))?;
+        socket.set_read_timeout(Some(timeout))?;
+        socket.connect(device.addr)?;
+
+        let mut client = Self {
+            socket,
+            device,
+            key: DEFAULT_KEY,
+            id: 0,
+            count: rand_count(),
+        };
+        client.auth()?;
+        Ok(client)
+    }
+
+    pub fn device(&self) -> &Device {
+        &self.device
+    }
+
+    fn auth(&mut self) -> Result<(), ClientError> {
+        let mut payload = [0u8; 0x50];
+        payload[0x04..0x14].fill(0x31);
+        payload[0x1e] = 0x01;
+        payload[0x2d] = 0x01;
+        payload[0x30..0x36].copy_from_slice(b"Test 1");
+
+        let response = self.request(AUTH_COMMAND, &payload)?;
+        if response.len() < 0x14 {
+            return Err(ClientError::InvalidPacket(
+                "authentication response is too short",
+            ));
+        }
+
+        self.id = u32::from_le_bytes(response[..0x04].try_into().unwrap());
+        self.key = response[0x04..0x14].try_into().unwrap();
+        Ok(())
+    }
+
+    fn request(&mut self, command: u16, payload: &[u8]) -> Result<Vec<u8>, ClientError> {
+        self.count = self.count.wrapping_add(1) | 0x8000;
+        let header = Header {
+            error: 0,
+            devtype: self.device.devtype,
+            command,
+            count: self.count,
+            mac: self.device.mac,
+            id: self.id,
+        };
+        self.socket.send(&header.encode(&self.key, payload))?;
+
+        let mut buf = [0u8; 2048];
+        let len = self
+            .socket
+            .recv(&mut buf)
+            .map_err(|e| match is_timeout(&e) {
+                true => ClientError::Timeout,
+                false => e.into(),
+            })?;
+
+        let (response, payload) = Header::decode(&buf[..len], &self.key)?;
+        match response.error {
+            0 => Ok(payload),
+            error => Err(ClientError::Device(error)),
+        }
+    }
+
+    /// Sends an RM command, returning its response data
+    fn rm_command(&mut self, command: u32, data: &[u8]) -> Result<Vec<u8>, ClientError> {
+        let rm4 = self.device.is_rm4();
+
+        let mut payload = Vec::new();
+        if rm4 {
+            payload.extend((data.len() as u16 + 4).to_le_bytes());
+        }
+        payload.extend(command.to_le_bytes());
+        payload.extend(data);
+
+        let response = self.request(RM_COMMAND, &payload)?;
+        let data = match rm4 {
+            true => {
+                let len = response
+                    .get(..2)
+                    .map(|len| u16::from_le_bytes([len[0], len[1]]) as usize + 2)
+                    .ok_or(ClientError::InvalidPacket("response is too short"))?;
+                response.get(6..len.min(response.len()))
+            }
+            false => response.get(4..),
+        };
+        data.map(<[u8]>::to_vec)
+            .ok_or(ClientError::InvalidPacket("response is too short"))
+    }
+
+    /// Transmits a recording
+    pub fn send(&mut self, recording: &Recording) -> Result<(), ClientError> {
+        self.rm_command(SEND_DATA, &recording.to_bytes())?;
+        Ok(())
+    }
+
+    /// Puts the device in IR learning mode
+    pub fn enter_learning(&mut self) -> Result<(), ClientError> {
+        self.rm_command(ENTER_LEARNING, &[])?;
+        Ok(())
+    }
+
+    /// Returns the learned code, if any
+    pub fn check_data(&mut self) -> Result<Option<Recording>, ClientError> {
+        match self.rm_command(CHECK_DATA, &[]) {
+            Ok(data) => Ok(Some(Recording::from_bytes(data.into())?)),
+            Err(ClientError::Device(STORAGE_ERROR | READ_ERROR)) => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Enters learning mode, and polls the device until it learns a code or the timeout expires
+    pub fn learn(&mut self, timeout: Duration) -> Result<Option<Recording>, ClientError> {
+        const POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+        self.enter_learning()?;
+        let deadline = Instant::now() + timeout;
+        loop {
+            if let Some(recording) = self.check_data()? {
+                return Ok(Some(recording));
+            }
+            if Instant::now() + POLL_INTERVAL > deadline {
+                return Ok(None);
+            }
+            thread::sleep(POLL_INTERVAL);
+        }
+    }
+}
+
+/// Packet counters start at an arbitrary value
+fn rand_count() -> u16 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map_or(0, |t| t.subsec_nanos() as u16)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::broadlink::mock::MockDevice;
+
+    #[test]
+    fn test_header() {
+        let header = Header {
+            error: 0,
+            devtype: 0x5216,
+            command: RM_COMMAND,
+            count: 0x8001,
+            mac: parse_mac("ec:0b:ae:9f:e2:ef").unwrap(),
+            id: 1,
+        };
+        let packet = header.encode(&DEFAULT_KEY, b"payload");
+        assert_eq!(packet.len(), Header::LEN + 16);
+
+        let (decoded, payload) = Header::decode(&packet, &DEFAULT_KEY).unwrap();
+        assert_eq!(decoded, header);
+        assert_eq!(&payload[..7], b"payload");
+
+        let mut corrupted = packet.clone();
+        corrupted[0x40] ^= 1;
+        assert!(matches!(
+            Header::decode(&corrupted, &DEFAULT_KEY),
+            Err(ClientError::InvalidPacket(_))
+        ));
+    }
+
+    #[test]
+    fn test_send_learn() {
+        for devtype in [0x5216, 0x2712] {
+            let mock = MockDevice::spawn(devtype).unwrap();
+
+            let devices = discover(mock.addr(), Duration::from_millis(500)).unwrap();
+            assert_eq!(devices.len(), 1);
+            assert_eq!(devices[0].devtype, devtype);
+            assert_eq!(devices[0].is_rm4(), devtype == 0x5216);
+
+            let mut client = Client::connect(devices[0].clone(), Duration::from_secs(1)).unwrap();
+            let off = include_str!("../../captures/off.ir");
+            let recording = Recording::from_bytes(hex::decode(off).unwrap().into()).unwrap();
+            client.send(&recording).unwrap();
+            assert_eq!(mock.received(), std::slice::from_ref(&recording));
+
+            assert_eq!(client.check_data().unwrap(), None);
+            mock.teach(recording.clone());
+            let learned = client.learn(Duration::from_secs(1)).unwrap();
+            assert_eq!(learned, Some(recording));
+        }
+    }
+
+    #[test]
+    fn test_mock_invalid_requests() {
+        let mock = MockDevice::spawn(0x5216).unwrap();
+        let devices = discover(mock.addr(), Duration::from_millis(500)).unwrap();
+        let mut client = Client::connect(devices[0].clone(), Duration::from_secs(1)).unwrap();
+
+        // Empty RM payloads and unknown commands are rejected without stopping the device
+        assert!(matches!(
+            client.request(RM_COMMAND, &[]),
+            Err(ClientError::Device(_))
+        ));
+        assert!(matches!(
+            client.request(u16::MAX, &[]),
+            Err(ClientError::Device(_))
+        ));
+        assert_eq!(client.check_data().unwrap(), None);
+    }
+}
@@ -0,0 +1,215 @@
+/** Local mock of an RM device, answering discovery, authentication and RM commands over UDP
+ * Every recording sent to the device is kept, and recordings taught to the mock are returned by
+ * learning mode, so that the client can be tested without hardware.
+ */
+use std::{
+    collections::VecDeque,
+    io,
+    net::{SocketAddr, UdpSocket},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc, Arc, Mutex,
+    },
+    thread,
+    time::Duration,
+};
+
+use super::{
+    client::{
+        self, Header, AUTH_COMMAND, CHECK_DATA, DEFAULT_KEY, DISCOVER_COMMAND, DISCOVER_LEN,
+        DISCOVER_RESPONSE_LEN, ENTER_LEARNING, RM_COMMAND, SEND_DATA, STORAGE_ERROR,
+    },
+    Recording,
+};
+
+const MAC: [u8; 6] = [0xec, 0x0b, 0xae, 0x9f, 0xe2, 0xef];
+const SESSION_KEY: [u8; 16] = *b"mock session key";
+const SESSION_ID: u32 = 0x1234;
+
+/// Error returned for commands the mock doesn't know
+const UNKNOWN_COMMAND: i16 = -3;
+/// Error returned for commands sent without authenticating first
+const NOT_AUTHENTICATED: i16 = -1;
+
+#[derive(Default)]
+struct State {
+    received: Vec<Recording>,
+    learnable: VecDeque<Recording>,
+    learning: bool,
+    /// Sessions opened before this one are no longer accepted
+    session: u32,
+    subscribers: Vec<mpsc::Sender<Recording>>,
+}
+
+pub struct MockDevice {
+    addr: SocketAddr,
+    state: Arc<Mutex<State>>,
+    stop: Arc<AtomicBool>,
+}
+
+impl MockDevice {
+    /// Starts a device of the given type, listening on a free local port
+    #[cfg(test)]
+    pub fn spawn(devtype: u16) -> io::Result<Self> {
+        MockDevice::bind(SocketAddr::from(([127, 0, 0, 1], 0)), devtype)
+    }
+
+    /// Starts a device of the given type, listening on the given address
+    pub fn bind(addr: SocketAddr, devtype: u16) -> io::Result<Self> {
+        let socket = UdpSocket::bind(addr)?;
+        socket.set_read_timeout(Some(Duration::from_millis(50)))?;
+
+        let device = Self {
+            addr: socket.local_addr()?,
+            state: Default::default(),
+            stop: Default::default(),
+        };
+
+        let server = Server {
+            socket,
+            devtype,
+            state: device.state.clone(),
+        };
+        let stop = device.stop.clone();
+        thread::spawn(move || {
+            let mut buf = [0u8; 2048];
+            while !stop.load(Ordering::Relaxed) {
+                if let Ok((len, peer)) = server.socket.recv_from(&mut buf) {
+                    server.handle(&buf[..len], peer);
+                }
+            }
+        });
+
+        Ok(device)
+    }
+
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    /// Recordings sent to the device
+    #[cfg(test)]
+    pub fn received(&self) -> Vec<Recording> {
+        self.state.lock().unwrap().received.clone()
+    }
+
+    /// Recordings sent to the device from now on
+    pub fn subscribe(&self) -> mpsc::Receiver<Recording> {
+        let (sender, receiver) = mpsc::channel();
+        self.state.lock().unwrap().subscribers.push(sender);
+        receiver
+    }
+
+    /// Forgets every session, as devices do when they reboot
+    #[cfg(test)]
+    pub fn reboot(&self) {
+        self.state.lock().unwrap().session += 1;
+    }
+
+    /// Queues a recording, learned the next time the device is in learning mode
+    pub fn teach(&self, recording: Recording) {
+        self.state.lock().unwrap().learnable.push_back(recording);
+    }
+}
+
+impl Drop for MockDevice {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+    }
+}
+
+struct Server {
+    socket: UdpSocket,
+    devtype: u16,
+    state: Arc<Mutex<State>>,
+}
+
+impl Server {
+    fn handle(&self, packet: &[u8], peer: SocketAddr) {
+        if packet.len() == DISCOVER_LEN && packet[0x26] == DISCOVER_COMMAND {
+            let mut response = vec![0u8; DISCOVER_RESPONSE_LEN];
+            response[0x34..0x36].copy_from_slice(&self.devtype.to_le_bytes());
+            response[0x3a..0x40].copy_from_slice(&client::reversed(MAC));
+            response[0x40..0x44].copy_from_slice(b"mock");
+            let _ = self.socket.send_to(&response, peer);
+            return;
+        }
+
+        // Authentication uses the default key, the session key afterwards
+        let (key, (request, payload)) = match Header::decode(packet, &DEFAULT_KEY) {
+            Ok((header, payload)) if header.command == AUTH_COMMAND => {
+                (DEFAULT_KEY, (header, payload))
+            }
+            _ => match Header::decode(packet, &SESSION_KEY) {
+                Ok(decoded) => (SESSION_KEY, decoded),
+                Err(_) => return,
+            },
+        };
+
+        let session_id = SESSION_ID + self.state.lock().unwrap().session;
+        let (error, payload) = match request.command {
+            AUTH_COMMAND => {
+                let mut payload = session_id.to_le_bytes().to_vec();
+                payload.extend(SESSION_KEY);
+                (0, payload)
+            }
+            RM_COMMAND if request.id != session_id => (NOT_AUTHENTICATED, Vec::new()),
+            RM_COMMAND => self.rm_command(&payload),
+            _ => (UNKNOWN_COMMAND, Vec::new()),
+        };
+
+        let response = Header {
+            error,
+            command: request.command.wrapping_add(0x384),
+            ..request
+        };
+        // Error responses only hold the header
+        let payload = if error == 0 { &payload[..] } else { &[] };
+        let _ = self.socket.send_to(&response.encode(&key, payload), peer);
+    }
+
+    fn rm_command(&self, payload: &[u8]) -> (i16, Vec<u8>) {
+        let rm4 = client::is_rm4(self.devtype);
+
+        let payload = if rm4 { payload.get(2..) } else { Some(payload) };
+        let command = match payload.and_then(|payload| payload.get(..4)) {
+            Some(command) => u32::from_le_bytes(command.try_into().unwrap()),
+            None => return (UNKNOWN_COMMAND, Vec::new()),
+        };
+        let data = &payload.unwrap()[4..];
+
+        let mut state = self.state.lock().unwrap();
+        let (error, data) = match command {
+            SEND_DATA => match Recording::from_bytes(data.to_vec().into()) {
+                Ok(recording) => {
+                    state
+                        .subscribers
+                        .retain(|subscriber| subscriber.send(recording.clone()).is_ok());
+                    state.received.push(recording);
+                    (0, Vec::new())
+                }
+                Err(_) => (UNKNOWN_COMMAND, Vec::new()),
+            },
+            ENTER_LEARNING => {
+                state.learning = true;
+                (0, Vec::new())
+            }
+            CHECK_DATA => match state.learnable.front() {
+                Some(_) if state.learning => {
+                    state.learning = false;
+                    (0, state.learnable.pop_front().unwrap().to_bytes().to_vec())
+                }
+                _ => (STORAGE_ERROR, Vec::new()),
+            },
+            _ => (UNKNOWN_COMMAND, Vec::new()),
+        };
+
+        let mut response = Vec::new();
+        if rm4 {
+            response.extend((data.len() as u16 + 4).to_le_bytes());
+        }
+        response.extend(command.to_le_bytes());
+        response.extend(data);
+        (error, response)
+    }
+}
@@ -0,0 +1,350 @@
+use std::{collections::BTreeSet, time::Duration};
+
+/**
+ * Implements encoding/decoding of payloads sent to a broadlink IR device
+ * Inspired from: https://github.com/haimkastner/broadlink-ir-converter/blob/master/src/index.ts
+ * Payload format from: https://github.com/mjg59/python-broadlink/blob/master/protocol.md
+ */
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+use thiserror::Error;
+
+pub mod client;
+pub mod mock;
+
+trait BroadlinkDuration {
+    fn to_broadlink(self) -> u16;
+    fn from_broadlink(broadlink_pulse: u16) -> Self;
+}
+
+impl BroadlinkDuration for std::time::Duration {
+    fn to_broadlink(self) -> u16 {
+        // Round through float to avoid rounding errors in conversion
+        (self.as_micros() as f64 * 269.0 / 8192.0).round() as u16
+    }
+
+    fn from_broadlink(broadlink_pulse: u16) -> Self {
+        // Round through float to avoid rounding errors in conversion
+        Self::from_nanos(
+            ((broadlink_pulse as f64) * 8192000.0 / 269.0).round() as _
+        )
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Transport {
+    Ir = 0x26,
+    Rf433 = 0xb2,
+    Rf315 = 0xd7,
+}
+
+impl Transport {
+    pub fn is_rf(self) -> bool {
+        self != Transport::Ir
+    }
+}
+
+/*
+Offset	Contents
+0x00	0x02
+0x01-0x03	0x00
+0x04	0x26 = IR, 0xb2 for RF 433Mhz, 0xd7 for RF 315Mhz
+0x05	repeat count, (0 = no repeat, 1 send twice, .....)
+0x06-0x07	Length of the following data in little endian
+0x08 ....	Pulse lengths in 2^-15 s units (µs * 269 / 8192 works very well)
+....	For IR codes, the pulse lengths should be paired as ON, OFF
+ */
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Recording {
+    pub repeat_count: u8,
+    pub transport: Transport,
+    // On-off pulse lengths in 2^-15 s ticks, as they're stored in the payload. Keeping the raw
+    // values lets any recording be re-encoded byte for byte, durations are computed on demand.
+    pub ticks: Vec<u16>,
+    // Indexes of the ticks stored as `00 hi lo` although they fit in a single byte, which some
+    // devices emit. They're written back the same way.
+    pub long_ticks: BTreeSet<usize>,
+}
+
+#[derive(Error, Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ParseError {
+    #[error("invalid transport type: {0}")]
+    InvalidTransport(u8),
+    #[error("truncated header: {0} bytes, expected at least 4")]
+    TruncatedHeader(usize),
+    #[error("truncated pulse at offset {0}")]
+    TruncatedPulse(usize),
+    #[error("pulse data is {declared} bytes long, but only {available} bytes follow the header")]
+    LengthMismatch { declared: usize, available: usize },
+    #[error("{0} non-zero bytes after the pulse data")]
+    TrailingData(usize),
+}
+
+impl Recording {
+
+    pub fn new_ir(pulses: Vec<Duration>) -> Self {
+        Self::new(Transport::Ir, pulses, 0)
+    }
+
+    /// 433MHz recording, sent once and then `repeat_count` more times
+    pub fn new_rf433(pulses: Vec<Duration>, repeat_count: u8) -> Self {
+        Self::new(Transport::Rf433, pulses, repeat_count)
+    }
+
+    /// 315MHz recording, sent once and then `repeat_count` more times
+    pub fn new_rf315(pulses: Vec<Duration>, repeat_count: u8) -> Self {
+        Self::new(Transport::Rf315, pulses, repeat_count)
+    }
+
+    fn new(transport: Transport, pulses: Vec<Duration>, repeat_count: u8) -> Self {
+        Self {
+            repeat_count,
+            transport,
+            ticks: pulses.into_iter().map(Duration::to_broadlink).collect(),
+            long_ticks: BTreeSet::new(),
+        }
+    }
+
+    /// Number of times the device sends the pulses
+    pub fn transmissions(&self) -> usize {
+        self.repeat_count as usize + 1
+    }
+
+    /// On-off pulse durations as they're transmitted, with the pulses sent once per transmission
+    pub fn transmitted_pulses(&self) -> Vec<Duration> {
+        let pulses = self.pulses();
+        let mut transmitted = Vec::with_capacity(pulses.len() * self.transmissions());
+        for _ in 0..self.transmissions() {
+            transmitted.extend_from_slice(&pulses);
+        }
+        transmitted
+    }
+
+    /// On-off pulse durations
+    pub fn pulses(&self) -> Vec<Duration> {
+        self.ticks
+            .iter()
+            .map(|&t| Duration::from_broadlink(t))
+            .collect()
+    }
+
+    pub fn to_pulses(&self) -> Vec<u32> {
+        self.pulses().iter().map(|p| p.as_micros() as _).collect()
+    }
+
+    pub fn to_bytes(&self) -> Bytes {
+        let mut b = BytesMut::new();
+        b.put_u8(self.transport as u8);
+        b.put_u8(self.repeat_count);
+
+        let mut pulses_buf = BytesMut::new();
+        for (i, &pulse) in self.ticks.iter().enumerate() {
+            // Zero can't be stored in a single byte, as it introduces a u16 value
+            if pulse > 0 && pulse < 256 && !self.long_ticks.contains(&i) {
+                pulses_buf.put_u8(pulse as _);
+            } else {
+                pulses_buf.put_u8(0);
+                pulses_buf.put_u16(pulse);
+            }
+        }
+
+        b.put_u16_le(pulses_buf.len() as _);
+        b.put(pulses_buf);
+        b.freeze()
+    }
+
+    /// Parses a payload, allowing the zero padding devices append after the pulse data
+    pub fn from_bytes(buf: Bytes) -> Result<Self, ParseError> {
+        const HEADER_LEN: usize = 4;
+
+        let mut buf = buf;
+        if buf.len() < HEADER_LEN {
+            return Err(ParseError::TruncatedHeader(buf.len()));
+        }
+
+        let transport = match buf.get_u8() {
+            0x26 => Transport::Ir,
+            0xb2 => Transport::Rf433,
+            0xd7 => Transport::Rf315,
+            x => return Err(ParseError::InvalidTransport(x)),
+        };
+
+        let repeat_count = buf.get_u8();
+        let declared = buf.get_u16_le() as usize;
+        if declared > buf.len() {
+            return Err(ParseError::LengthMismatch {
+                declared,
+                available: buf.len(),
+            });
+        }
+
+        let padding = buf.split_off(declared);
+        let trailing = padding.iter().filter(|&&b| b != 0).count();
+        if trailing > 0 {
+            return Err(ParseError::TrailingData(trailing));
+        }
+
+        let mut ticks = Vec::with_capacity(declared);
+        let mut long_ticks = BTreeSet::new();
+        while buf.has_remaining() {
+            let offset = HEADER_LEN + declared - buf.remaining();
+            let mut value: u16 = buf.get_u8() as u16;
+
+            if value == 0 {
+                // This indicates that the value didn't fit in a single byte and is stored as a u16_be
+                if buf.remaining() < 2 {
+                    return Err(ParseError::TruncatedPulse(offset));
+                }
+                value = buf.get_u16();
+                if value > 0 && value < 256 {
+                    long_ticks.insert(ticks.len());
+                }
+            }
+
+            ticks.push(value);
+        }
+
+        Ok(Recording {
+            repeat_count,
+            transport,
+            ticks,
+            long_ticks,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    #[test]
+    fn test_decode() {
+        let message = hex_literal::hex!("2600ca008b8f1035101211341013101210121112103510121112103510121112101211340f360f121134111210121112103510351035103510341134113411341134113410351035103510351035103510351035103510341134113411121035101210350f3510a88c8e11121035101211341134113410351012113411341112103510351035101210121134111210351035103510121013101210121112101210130f13101210131012101211121012101310121013101210121112101211121035101211341112101210000d05");
+        let pulses = [
+            4233, 4354, 487, 1614, 487, 548, 517, 1583, 487, 578, 487, 548, 487, 548, 517, 548,
+            487, 1614, 487, 548, 517, 548, 487, 1614, 487, 548, 517, 548, 487, 548, 517, 1583, 456,
+            1644, 456, 548, 517, 1583, 517, 548, 487, 548, 517, 548, 487, 1614, 487, 1614, 487,
+            1614, 487, 1614, 487, 1583, 517, 1583, 517, 1583, 517, 1583, 517, 1583, 517, 1583, 487,
+            1614, 487, 1614, 487, 1614, 487, 1614, 487, 1614, 487, 1614, 487, 1614, 487, 1614, 487,
+            1614, 487, 1583, 517, 1583, 517, 1583, 517, 548, 487, 1614, 487, 548, 487, 1614, 456,
+            1614, 487, 5116, 4263, 4324, 517, 548, 487, 1614, 487, 548, 517, 1583, 517, 1583, 517,
+            1583, 487, 1614, 487, 548, 517, 1583, 517, 1583, 517, 548, 487, 1614, 487, 1614, 487,
+            1614, 487, 548, 487, 548, 517, 1583, 517, 548, 487, 1614, 487, 1614, 487, 1614, 487,
+            548, 487, 578, 487, 548, 487, 548, 517, 548, 487, 548, 487, 578, 456, 578, 487, 548,
+            487, 578, 487, 548, 487, 548, 517, 548, 487, 548, 487, 578, 487, 548, 487, 578, 487,
+            548, 487, 548, 517, 548, 487, 548, 517, 548, 487, 1614, 487, 548, 517, 1583, 517, 548,
+            487, 548, 487, 35965,
+        ];
+        let message = Bytes::copy_from_slice(&message);
+
+        let decoded = Recording::from_bytes(message.clone()).unwrap();
+        assert_eq!(decoded.transport, Transport::Ir);
+        assert_eq!(decoded.repeat_count, 0);
+
+        for (i, (pulse, &ref_pulse)) in decoded.pulses().iter().zip(pulses.iter()).enumerate() {
+            assert_eq!(
+                pulse.as_micros() as u16,
+                ref_pulse,
+                "pulse {} does not match",
+                i
+            );
+        }
+        assert_eq!(decoded.pulses().len(), pulses.len());
+
+        let encoded = decoded.to_bytes();
+        assert_eq!(hex::encode(encoded), hex::encode(message));
+    }
+
+    #[test]
+    fn test_ticks_round_trip() {
+        // Every tick value, in both the single byte and u16 encodings, including zero
+        let ticks: Vec<u16> = (0..=u16::MAX)
+            .step_by(7)
+            .chain([1, 255, 256, u16::MAX])
+            .collect();
+        let recording = Recording {
+            repeat_count: 3,
+            transport: Transport::Rf433,
+            ticks,
+            long_ticks: BTreeSet::new(),
+        };
+
+        let bytes = recording.to_bytes();
+        let decoded = Recording::from_bytes(bytes.clone()).unwrap();
+        assert_eq!(decoded, recording);
+        assert_eq!(decoded.to_bytes(), bytes);
+
+        // Durations are only a view, they don't alter the stored ticks
+        assert_eq!(Recording::new_ir(decoded.pulses()).ticks, decoded.ticks);
+    }
+
+    #[test]
+    fn test_long_ticks() {
+        // Ticks that fit in a byte but were sent in the u16 form are written back in that form
+        let bytes = Bytes::from_static(&[
+            0x26, 0x00, 0x07, 0x00, 0x10, 0x00, 0x00, 0x20, 0x00, 0x0d, 0x05,
+        ]);
+        let recording = Recording::from_bytes(bytes.clone()).unwrap();
+        assert_eq!(recording.ticks, [0x10, 0x20, 0x0d05]);
+        assert_eq!(recording.long_ticks, BTreeSet::from([1]));
+        assert_eq!(recording.to_bytes(), bytes);
+
+        // Without them, every tick takes the shortest form
+        let recording = Recording {
+            long_ticks: BTreeSet::new(),
+            ..recording
+        };
+        assert_eq!(recording.to_bytes()[2..4], [0x05, 0x00]);
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        let parse = |bytes: &[u8]| Recording::from_bytes(Bytes::copy_from_slice(bytes));
+
+        assert_eq!(parse(&[]), Err(ParseError::TruncatedHeader(0)));
+        assert_eq!(
+            parse(&[0x26, 0x00, 0x02]),
+            Err(ParseError::TruncatedHeader(3))
+        );
+        assert_eq!(
+            parse(&[0x42, 0x00, 0x00, 0x00]),
+            Err(ParseError::InvalidTransport(0x42))
+        );
+        assert_eq!(
+            parse(&[0x26, 0x00, 0x05, 0x00, 0x10, 0x20]),
+            Err(ParseError::LengthMismatch {
+                declared: 5,
+                available: 2
+            })
+        );
+        // A u16 marker without its value, within the declared length
+        assert_eq!(
+            parse(&[0x26, 0x00, 0x03, 0x00, 0x10, 0x00, 0x0d]),
+            Err(ParseError::TruncatedPulse(5))
+        );
+        assert_eq!(
+            parse(&[0x26, 0x00, 0x01, 0x00, 0x10, 0x00, 0x0d, 0x05]),
+            Err(ParseError::TrailingData(2))
+        );
+
+        // Zero padding after the pulse data is ignored
+        let recording =
+            parse(&[0x26, 0x00, 0x04, 0x00, 0x10, 0x00, 0x0d, 0x05, 0x00, 0x00]).unwrap();
+        assert_eq!(recording.ticks, [0x10, 0x0d05]);
+    }
+
+    #[test]
+    fn test_rf() {
+        let pulses = [350, 1050, 1050, 350, 350, 10850].map(Duration::from_micros);
+        let recording = Recording::new_rf433(pulses.to_vec(), 2);
+        assert!(recording.transport.is_rf());
+        assert_eq!(recording.transmissions(), 3);
+        assert_eq!(recording.transmitted_pulses().len(), pulses.len() * 3);
+
+        let bytes = recording.to_bytes();
+        assert_eq!(bytes[..2], [0xb2, 2]);
+        assert_eq!(Recording::from_bytes(bytes).unwrap(), recording);
+
+        let recording = Recording::new_rf315(pulses.to_vec(), 0);
+        assert_eq!(recording.to_bytes()[0], 0xd7);
+        assert_eq!(recording.transmitted_pulses(), recording.pulses());
+    }
+}
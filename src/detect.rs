@@ -0,0 +1,216 @@
+/** Tries every known decoder on a capture, to find out which protocol it uses */
+use std::time::Duration;
+
+use anyhow::anyhow;
+use irp::{InfraredData, Irp, NFA};
+
+use crate::{
+    broadlink::Recording,
+    protocol::{self, Protocol},
+    pwm::{Codec, Rule},
+};
+
+/// A successful decode
+#[derive(Debug, Clone)]
+pub struct Match {
+    /// How closely the pulse timings matched the decoder's nominal timings, from 0.0 to 1.0
+    pub confidence: f32,
+    /// Human readable decoded payload
+    pub payload: String,
+}
+
+/// Outcome of a single decoder on a capture
+#[derive(Debug)]
+pub struct Detection {
+    pub decoder: String,
+    pub result: anyhow::Result<Match>,
+}
+
+/// Something that can recognize captures
+pub trait Detector {
+    fn name(&self) -> String;
+
+    fn detect(&self, recording: &Recording) -> anyhow::Result<Match>;
+}
+
+/// Adapts a heat pump protocol into a detector
+struct ProtocolDetector(Box<dyn Protocol>);
+
+impl Detector for ProtocolDetector {
+    fn name(&self) -> String {
+        self.0.name().into()
+    }
+
+    fn detect(&self, recording: &Recording) -> anyhow::Result<Match> {
+        Ok(Match {
+            payload: self.0.describe(&recording.pulses())?,
+            confidence: self.0.confidence(&recording.pulses()),
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
+enum NecPulse {
+    Unit,
+    Triple,
+    RepeatSpace,
+    HeaderSpace,
+    Header,
+}
+
+/// 48-bit NEC variant, decoded through its IRP definition
+pub struct Nec {
+    nfa: NFA,
+    codec: Codec<NecPulse>,
+}
+
+impl Nec {
+    const IRP_48_NEC1: &str = "{38.4k,564}<1,-1|1,-3>(16,-8,D:8,S:8,F:8,~F:8,E:8,~E:8,1,^108m,(16,-4,1,^108m)*)[D:0..255,S:0..255=255-D,F:0..255,E:0..255]";
+    const UNIT: u64 = 564;
+
+    pub fn new() -> Self {
+        let nfa = Irp::parse(Nec::IRP_48_NEC1)
+            .expect("irp parse")
+            .compile()
+            .expect("irp compile");
+
+        // Only used to score timings, the IRP decoder does the actual decoding
+        let codec = Codec::new(
+            [
+                (NecPulse::Unit, 1),
+                (NecPulse::Triple, 3),
+                (NecPulse::RepeatSpace, 4),
+                (NecPulse::HeaderSpace, 8),
+                (NecPulse::Header, 16),
+            ]
+            .into_iter()
+            .map(|(p, units)| (p, Rule::new(Duration::from_micros(units * Nec::UNIT)))),
+        );
+
+        Self { nfa, codec }
+    }
+
+    /// Decodes a recording into its IRP fields, sorted by name
+    pub fn decode(&self, recording: &Recording) -> Option<Vec<(String, i64)>> {
+        let mut decoder = self.nfa.decoder(100, 30, 20000);
+        for pulse in InfraredData::from_u32_slice(&recording.to_pulses()) {
+            decoder.input(pulse);
+        }
+
+        let mut decoded: Vec<_> = decoder.get()?.into_iter().collect();
+        decoded.sort_by_key(|f| f.0.clone());
+        Some(decoded)
+    }
+}
+
+impl Detector for Nec {
+    fn name(&self) -> String {
+        "nec48".into()
+    }
+
+    fn detect(&self, recording: &Recording) -> anyhow::Result<Match> {
+        let fields = self
+            .decode(recording)
+            .ok_or_else(|| anyhow!("no NEC frame found"))?;
+
+        let payload = fields
+            .iter()
+            .map(|(field, value)| format!("{}: {}", field, value))
+            .collect::<Vec<_>>()
+            .join(" ");
+
+        // The trailing gap depends on the frame length, leave it out of the timing score
+        let pulses = recording.pulses();
+        let timed = &pulses[..pulses.len().saturating_sub(1)];
+        Ok(Match {
+            confidence: self.codec.confidence(timed.iter().copied()),
+            payload,
+        })
+    }
+}
+
+/// Returns every known detector: all registered protocols, followed by generic IR protocols. The
+/// configured protocol (e.g. with calibrated timings or recovery) replaces its default instance.
+pub fn detectors(configured: Box<dyn Protocol>) -> Vec<Box<dyn Detector>> {
+    let mut configured = Some(configured);
+    let mut detectors: Vec<Box<dyn Detector>> = protocol::all()
+        .into_iter()
+        .map(|p| match &configured {
+            Some(c) if c.name() == p.name() => configured.take().unwrap(),
+            _ => p,
+        })
+        .map(|p| Box::new(ProtocolDetector(p)) as _)
+        .collect();
+    detectors.push(Box::new(Nec::new()));
+    detectors
+}
+
+/// Runs every detector on a recording. Matches come first, ordered by decreasing confidence,
+/// followed by the reason each other detector failed.
+pub fn detect(detectors: &[Box<dyn Detector>], recording: &Recording) -> Vec<Detection> {
+    let mut detections: Vec<_> = detectors
+        .iter()
+        .map(|d| Detection {
+            decoder: d.name(),
+            result: d.detect(recording),
+        })
+        .collect();
+
+    detections.sort_by(|a, b| {
+        let confidence = |d: &Detection| d.result.as_ref().map_or(-1.0, |m| m.confidence);
+        confidence(b).total_cmp(&confidence(a))
+    });
+
+    detections
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lennox::Lennox;
+
+    fn off() -> Recording {
+        let off = include_str!("../captures/off.ir");
+        Recording::from_bytes(hex::decode(off).unwrap().into()).unwrap()
+    }
+
+    #[test]
+    fn test_detect_lennox() {
+        let recording = off();
+        let detectors = detectors(Box::new(Lennox::new()));
+
+        let detections = detect(&detectors, &recording);
+        assert_eq!(detections.len(), detectors.len());
+
+        let best = &detections[0];
+        assert_eq!(best.decoder, "lennox");
+        let best = best.result.as_ref().unwrap();
+        assert!(best.confidence > 0.5);
+        assert!(best.payload.contains("a12347ffffeb"));
+
+        let nec = detections.iter().find(|d| d.decoder == "nec48").unwrap();
+        assert!(nec.result.is_err());
+    }
+
+    #[test]
+    fn test_detect_configured() {
+        // The space of bit 10 is damaged, only recovery decodes it
+        let mut pulses = off().pulses();
+        pulses[77] = Duration::from_micros(850);
+        let recording = Recording::new_ir(pulses);
+
+        let lennox = |protocol: Lennox| {
+            detect(&detectors(Box::new(protocol)), &recording)
+                .into_iter()
+                .find(|d| d.decoder == "lennox")
+                .unwrap()
+                .result
+        };
+        assert!(lennox(Lennox::new()).is_err());
+
+        let mut recovering = Lennox::new();
+        recovering.set_recovery(true).unwrap();
+        let detected = lennox(recovering).unwrap();
+        assert!(detected.payload.contains("a12347ffffeb"));
+    }
+}
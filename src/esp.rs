@@ -0,0 +1,156 @@
+/** Raw code export for ESP transmitters: ESPHome and IRremoteESP8266
+ * ESPHome's transmit_raw takes signed durations in microseconds, positive for marks and negative
+ * for spaces, with the carrier frequency in its own field.
+ * IRremoteESP8266's sendRaw takes unsigned durations alternating between marks and spaces,
+ * starting with a mark, and the carrier frequency in kHz.
+ */
+use std::{fmt::Write, time::Duration};
+
+use crate::protocol::{self, Protocol};
+
+/// ESPHome `remote_transmitter.transmit_raw` action sending the pulses
+pub fn esphome_action(frequency: u32, pulses: &[Duration]) -> String {
+    let code: Vec<_> = pulses
+        .iter()
+        .enumerate()
+        .map(|(i, p)| {
+            let micros = p.as_micros() as i64;
+            if i % 2 == 0 {
+                micros
+            } else {
+                -micros
+            }
+        })
+        .map(|micros| micros.to_string())
+        .collect();
+
+    format!(
+        "- remote_transmitter.transmit_raw:\n    carrier_frequency: {}\n    code: [{}]\n",
+        esphome_frequency(frequency),
+        code.join(", ")
+    )
+}
+
+/// ESPHome accepts unit suffixes, keep whole kHz readable
+fn esphome_frequency(frequency: u32) -> String {
+    if frequency.is_multiple_of(1000) {
+        format!("{}kHz", frequency / 1000)
+    } else {
+        format!("{}Hz", frequency)
+    }
+}
+
+/// C array holding the pulses, and the IRremoteESP8266 call sending it
+pub fn irremote_array(name: &str, frequency: u32, pulses: &[Duration]) -> String {
+    let values: Vec<_> = pulses
+        .iter()
+        .map(|p| p.as_micros().min(u16::MAX as _).to_string())
+        .collect();
+    let khz = (frequency as f64 / 1000.0).round() as u32;
+
+    let lines: Vec<_> = values
+        .chunks(16)
+        .map(|line| format!("  {},", line.join(", ")))
+        .collect();
+
+    format!(
+        "// irsend.sendRaw({name}, {len}, {khz});\nconst uint16_t {name}[{len}] = {{\n{lines}\n}};\n",
+        name = name,
+        len = values.len(),
+        khz = khz,
+        lines = lines.join("\n"),
+    )
+}
+
+/// Generates an ESPHome configuration block with a template button for every state
+pub fn gen_esphome(protocol: &dyn Protocol) -> anyhow::Result<()> {
+    print!("{}", esphome_buttons(protocol)?);
+    Ok(())
+}
+
+fn esphome_buttons(protocol: &dyn Protocol) -> anyhow::Result<String> {
+    let mut out = String::new();
+    writeln!(out, "button:")?;
+
+    for (name, state) in protocol::named_states(protocol) {
+        let pulses = protocol.encode(&state)?;
+        writeln!(out, "  - platform: template")?;
+        writeln!(out, "    name: \"{} {}\"", protocol.name(), name)?;
+        writeln!(out, "    on_press:")?;
+        for line in esphome_action(protocol.carrier_frequency(), &pulses).lines() {
+            writeln!(out, "      {}", line)?;
+        }
+    }
+
+    Ok(out)
+}
+
+/// Generates a C header with an IRremoteESP8266 raw array for every state
+pub fn gen_irremote(protocol: &dyn Protocol) -> anyhow::Result<()> {
+    print!("{}", irremote_header(protocol)?);
+    Ok(())
+}
+
+fn irremote_header(protocol: &dyn Protocol) -> anyhow::Result<String> {
+    let mut out = String::new();
+    writeln!(
+        out,
+        "// {} {}",
+        protocol.manufacturer(),
+        protocol.models().join(", ")
+    )?;
+    writeln!(out, "#pragma once")?;
+    writeln!(out, "#include <stdint.h>")?;
+
+    for (name, state) in protocol::named_states(protocol) {
+        let pulses = protocol.encode(&state)?;
+        let name = format!("{}_{}", protocol.name(), name);
+        writeln!(out)?;
+        write!(
+            out,
+            "{}",
+            irremote_array(&name, protocol.carrier_frequency(), &pulses)
+        )?;
+    }
+
+    Ok(out)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::lennox::Lennox;
+
+    #[test]
+    fn test_esphome_action() {
+        let pulses = [4000, 4000, 500, 1500, 500].map(Duration::from_micros);
+        assert_eq!(
+            esphome_action(38000, &pulses),
+            "- remote_transmitter.transmit_raw:\n    carrier_frequency: 38kHz\n    code: [4000, -4000, 500, -1500, 500]\n"
+        );
+        assert!(esphome_action(36700, &pulses).contains("carrier_frequency: 36700Hz"));
+    }
+
+    #[test]
+    fn test_irremote_array() {
+        let pulses = [4000, 4000, 500, 1500, 500].map(Duration::from_micros);
+        assert_eq!(
+            irremote_array("rawData", 38000, &pulses),
+            "// irsend.sendRaw(rawData, 5, 38);\nconst uint16_t rawData[5] = {\n  4000, 4000, 500, 1500, 500,\n};\n"
+        );
+    }
+
+    #[test]
+    fn test_tables() {
+        let lennox = Lennox::new();
+        let states = protocol::named_states(&lennox).len();
+
+        let yaml = esphome_buttons(&lennox).unwrap();
+        assert_eq!(yaml.matches("platform: template").count(), states);
+        assert!(yaml.contains("name: \"lennox heat_auto_24\""));
+
+        let header = irremote_header(&lennox).unwrap();
+        assert_eq!(header.matches("const uint16_t").count(), states);
+        assert!(header.contains("const uint16_t lennox_heat_auto_24[200] = {"));
+    }
+}
@@ -0,0 +1,340 @@
+/** Reading and writing of Flipper Zero infrared files (.ir)
+ * Format description: https://github.com/flipperdevices/flipperzero-firmware/blob/dev/documentation/file_formats/InfraredFileFormats.md
+ * Files hold named signals, either raw (on-off durations in microseconds) or parsed (a protocol
+ * known to the Flipper, with its address and command).
+ */
+use std::{fmt, str::FromStr, time::Duration};
+
+use thiserror::Error;
+
+use crate::protocol::{self, Protocol};
+
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum FlipperError {
+    #[error("not a Flipper infrared file")]
+    InvalidHeader,
+    #[error("invalid line: {0}")]
+    InvalidLine(String),
+    #[error("signal {signal} is missing its {field}")]
+    MissingField { signal: String, field: &'static str },
+    #[error("invalid {field}: {value}")]
+    InvalidValue { field: &'static str, value: String },
+    #[error("unknown signal type: {0}")]
+    UnknownType(String),
+    #[error("signal {0} is a parsed {1} signal, only raw signals hold pulses")]
+    NotRaw(String, String),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum SignalData {
+    Raw {
+        /// Carrier frequency, in Hz
+        frequency: u32,
+        /// Carrier duty cycle, from 0.0 to 1.0
+        duty_cycle: f32,
+        /// On-off pulse durations
+        pulses: Vec<Duration>,
+    },
+    Parsed {
+        protocol: String,
+        address: String,
+        command: String,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Signal {
+    pub name: String,
+    pub data: SignalData,
+}
+
+impl Signal {
+    const DUTY_CYCLE: f32 = 0.33;
+
+    pub fn new_raw(name: impl Into<String>, frequency: u32, pulses: Vec<Duration>) -> Self {
+        Self {
+            name: name.into(),
+            data: SignalData::Raw {
+                frequency,
+                duty_cycle: Signal::DUTY_CYCLE,
+                pulses,
+            },
+        }
+    }
+
+    /// On-off pulse durations of a raw signal
+    pub fn pulses(&self) -> Result<&[Duration], FlipperError> {
+        match &self.data {
+            SignalData::Raw { pulses, .. } => Ok(pulses),
+            SignalData::Parsed { protocol, .. } => {
+                Err(FlipperError::NotRaw(self.name.clone(), protocol.clone()))
+            }
+        }
+    }
+}
+
+/// Contents of a .ir file
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct IrFile {
+    pub signals: Vec<Signal>,
+}
+
+impl IrFile {
+    const FILETYPE: &'static str = "IR signals file";
+    const LIBRARY_FILETYPE: &'static str = "IR library file";
+    const VERSION: &'static str = "1";
+}
+
+impl fmt::Display for IrFile {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Filetype: {}", IrFile::FILETYPE)?;
+        writeln!(f, "Version: {}", IrFile::VERSION)?;
+
+        for signal in &self.signals {
+            writeln!(f, "# ")?;
+            writeln!(f, "name: {}", signal.name)?;
+            match &signal.data {
+                SignalData::Raw {
+                    frequency,
+                    duty_cycle,
+                    pulses,
+                } => {
+                    let data: Vec<_> = pulses.iter().map(|p| p.as_micros().to_string()).collect();
+                    writeln!(f, "type: raw")?;
+                    writeln!(f, "frequency: {}", frequency)?;
+                    writeln!(f, "duty_cycle: {:.6}", duty_cycle)?;
+                    writeln!(f, "data: {}", data.join(" "))?;
+                }
+                SignalData::Parsed {
+                    protocol,
+                    address,
+                    command,
+                } => {
+                    writeln!(f, "type: parsed")?;
+                    writeln!(f, "protocol: {}", protocol)?;
+                    writeln!(f, "address: {}", address)?;
+                    writeln!(f, "command: {}", command)?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Fields of a signal, as they're read
+#[derive(Default)]
+struct SignalFields<'a> {
+    name: &'a str,
+    kind: Option<&'a str>,
+    frequency: Option<&'a str>,
+    duty_cycle: Option<&'a str>,
+    data: Vec<&'a str>,
+    protocol: Option<&'a str>,
+    address: Option<&'a str>,
+    command: Option<&'a str>,
+}
+
+impl SignalFields<'_> {
+    fn into_signal(self) -> Result<Signal, FlipperError> {
+        let name = self.name.to_string();
+        let missing = |field| FlipperError::MissingField {
+            signal: name.clone(),
+            field,
+        };
+
+        let data = match self.kind.ok_or_else(|| missing("type"))? {
+            "raw" => SignalData::Raw {
+                frequency: parse_value(
+                    "frequency",
+                    self.frequency.ok_or_else(|| missing("frequency"))?,
+                )?,
+                duty_cycle: parse_value(
+                    "duty_cycle",
+                    self.duty_cycle.ok_or_else(|| missing("duty_cycle"))?,
+                )?,
+                pulses: self
+                    .data
+                    .iter()
+                    .flat_map(|line| line.split_whitespace())
+                    .map(|v| parse_value("data", v).map(Duration::from_micros))
+                    .collect::<Result<_, _>>()?,
+            },
+            "parsed" => SignalData::Parsed {
+                protocol: self.protocol.ok_or_else(|| missing("protocol"))?.into(),
+                address: self.address.ok_or_else(|| missing("address"))?.into(),
+                command: self.command.ok_or_else(|| missing("command"))?.into(),
+            },
+            other => return Err(FlipperError::UnknownType(other.into())),
+        };
+
+        Ok(Signal { name, data })
+    }
+}
+
+fn parse_value<T: FromStr>(field: &'static str, value: &str) -> Result<T, FlipperError> {
+    value.parse().map_err(|_| FlipperError::InvalidValue {
+        field,
+        value: value.into(),
+    })
+}
+
+impl FromStr for IrFile {
+    type Err = FlipperError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut lines = s
+            .lines()
+            .map(str::trim)
+            .filter(|l| !l.is_empty() && !l.starts_with('#'));
+
+        // Universal remote libraries share the format of regular signal files
+        match lines.next().and_then(|l| l.strip_prefix("Filetype:")) {
+            Some(filetype)
+                if [IrFile::FILETYPE, IrFile::LIBRARY_FILETYPE].contains(&filetype.trim()) => {}
+            _ => return Err(FlipperError::InvalidHeader),
+        }
+
+        let mut signals = Vec::new();
+        let mut current: Option<SignalFields> = None;
+
+        for line in lines {
+            let (key, value) = line
+                .split_once(':')
+                .ok_or_else(|| FlipperError::InvalidLine(line.into()))?;
+            let value = value.trim();
+
+            if key == "name" {
+                if let Some(fields) = current.take() {
+                    signals.push(fields.into_signal()?);
+                }
+                current = Some(SignalFields {
+                    name: value,
+                    ..Default::default()
+                });
+                continue;
+            }
+
+            let fields = match current.as_mut() {
+                Some(fields) => fields,
+                // The version and any other header field come before the first signal
+                None => continue,
+            };
+
+            match key {
+                "type" => fields.kind = Some(value),
+                "frequency" => fields.frequency = Some(value),
+                "duty_cycle" => fields.duty_cycle = Some(value),
+                // Long captures may be split over several data lines
+                "data" => fields.data.push(value),
+                "protocol" => fields.protocol = Some(value),
+                "address" => fields.address = Some(value),
+                "command" => fields.command = Some(value),
+                _ => return Err(FlipperError::InvalidLine(line.into())),
+            }
+        }
+
+        if let Some(fields) = current {
+            signals.push(fields.into_signal()?);
+        }
+
+        Ok(IrFile { signals })
+    }
+}
+
+/// Generates a .ir file holding a raw signal for every state
+pub fn gen_ir_file(protocol: &dyn Protocol) -> anyhow::Result<()> {
+    print!("{}", ir_file(protocol)?);
+    Ok(())
+}
+
+fn ir_file(protocol: &dyn Protocol) -> anyhow::Result<IrFile> {
+    let signals = protocol::named_states(protocol)
+        .into_iter()
+        .map(|(name, state)| {
+            let pulses = protocol.encode(&state)?;
+            Ok(Signal::new_raw(name, protocol.carrier_frequency(), pulses))
+        })
+        .collect::<anyhow::Result<_>>()?;
+
+    Ok(IrFile { signals })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{lennox::Lennox, state::Mode};
+
+    const FILE: &str = "Filetype: IR signals file
+Version: 1
+#
+name: Power
+type: parsed
+protocol: NEC
+address: 07 00 00 00
+command: 02 00 00 00
+#
+name: Cool
+type: raw
+frequency: 38000
+duty_cycle: 0.330000
+data: 4400 4300 550 1600 550 500
+data: 550 1600
+";
+
+    #[test]
+    fn test_parse() {
+        let file: IrFile = FILE.parse().unwrap();
+        assert_eq!(file.signals.len(), 2);
+        assert_eq!(
+            file.signals[0].data,
+            SignalData::Parsed {
+                protocol: "NEC".into(),
+                address: "07 00 00 00".into(),
+                command: "02 00 00 00".into(),
+            }
+        );
+        assert!(file.signals[0].pulses().is_err());
+
+        assert_eq!(file.signals[1].name, "Cool");
+        let pulses = file.signals[1].pulses().unwrap();
+        assert_eq!(pulses.len(), 8);
+        assert_eq!(pulses[0], Duration::from_micros(4400));
+
+        assert_eq!(file.to_string().parse::<IrFile>().unwrap(), file);
+    }
+
+    #[test]
+    fn test_export_decode() {
+        let lennox = Lennox::new();
+        let file: IrFile = ir_file(&lennox).unwrap().to_string().parse().unwrap();
+
+        let signal = file
+            .signals
+            .iter()
+            .find(|s| s.name == "cool_max_22")
+            .unwrap();
+        let state = lennox.decode(signal.pulses().unwrap()).unwrap();
+        assert_eq!(state.mode, Mode::Cool);
+    }
+
+    #[test]
+    fn test_errors() {
+        assert_eq!(
+            "Filetype: Flipper SubGhz RAW File".parse::<IrFile>(),
+            Err(FlipperError::InvalidHeader)
+        );
+        assert_eq!(
+            "Filetype: IR signals file\nname: a\nfrequency: 38000".parse::<IrFile>(),
+            Err(FlipperError::MissingField {
+                signal: "a".into(),
+                field: "type"
+            })
+        );
+        assert!(matches!(
+            "Filetype: IR signals file\nname: a\ntype: raw\nfrequency: 38000\nduty_cycle: 0.33\ndata: 1 x"
+                .parse::<IrFile>(),
+            Err(FlipperError::InvalidValue { field: "data", .. })
+        ));
+    }
+}
@@ -0,0 +1,222 @@
+/** Home Assistant Broadlink learned codes storage (.storage/broadlink_remote_<mac>_codes)
+ * Commands are grouped by device, each command holds a base64 code, or a list of codes for
+ * commands that toggle between several codes.
+ */
+use std::io::{self, Read};
+
+use anyhow::bail;
+use bytes::Bytes;
+use clap::Parser;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+
+use crate::{
+    broadlink::Recording,
+    protocol::{self, Protocol},
+    state::ControlState,
+};
+
+#[derive(Clone, Parser, Debug)]
+pub enum HaStorageCommand {
+    /// Print a storage file holding every state under the given device
+    Export {
+        /// Device the commands are stored under, as used by the remote.send_command service
+        #[clap(long, default_value = "heatpump")]
+        device: String,
+
+        /// Prefix added to every command name
+        #[clap(long, default_value = "")]
+        prefix: String,
+
+        /// MAC address of the Broadlink remote (e.g. 34ea34b43c5d), used to name the storage
+        #[clap(long)]
+        mac: Option<String>,
+
+        /// Existing storage file to add the device to, other devices are kept as they are
+        #[clap(long)]
+        storage: Option<std::path::PathBuf>,
+    },
+
+    /// Decode every command of a storage file read from stdin
+    Audit,
+}
+
+/// Contents of the storage file
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Storage {
+    pub version: u32,
+    #[serde(default)]
+    pub minor_version: u32,
+    pub key: String,
+    /// device -> command -> code or list of codes
+    pub data: Map<String, Value>,
+}
+
+impl Storage {
+    const VERSION: u32 = 1;
+    const MINOR_VERSION: u32 = 1;
+
+    pub fn new(mac: &str) -> Self {
+        let mac: String = mac
+            .chars()
+            .filter(char::is_ascii_hexdigit)
+            .collect::<String>()
+            .to_lowercase();
+
+        Self {
+            version: Storage::VERSION,
+            minor_version: Storage::MINOR_VERSION,
+            key: format!("broadlink_remote_{}_codes", mac),
+            data: Map::new(),
+        }
+    }
+
+    /// Every code in the file, as (device, command, code). Toggle commands yield each of their
+    /// codes, with its index appended to the command name.
+    pub fn codes(&self) -> anyhow::Result<Vec<(String, String, String)>> {
+        let mut codes = Vec::new();
+
+        for (device, commands) in &self.data {
+            let commands = match commands {
+                Value::Object(commands) => commands,
+                _ => bail!("device {} doesn't hold commands", device),
+            };
+
+            for (command, code) in commands {
+                match code {
+                    Value::String(code) => {
+                        codes.push((device.clone(), command.clone(), code.clone()))
+                    }
+                    Value::Array(toggle) => {
+                        for (i, code) in toggle.iter().enumerate() {
+                            let code = code.as_str().ok_or_else(|| {
+                                anyhow::anyhow!("{}/{} holds an invalid code", device, command)
+                            })?;
+                            let command = format!("{}[{}]", command, i);
+                            codes.push((device.clone(), command, code.into()));
+                        }
+                    }
+                    _ => bail!("{}/{} holds an invalid code", device, command),
+                }
+            }
+        }
+
+        Ok(codes)
+    }
+}
+
+/// Adds every state of the protocol to the storage, under the given device
+pub fn export_states(
+    protocol: &dyn Protocol,
+    storage: &mut Storage,
+    device: &str,
+    prefix: &str,
+) -> anyhow::Result<()> {
+    let mut commands = Map::new();
+    for (name, state) in protocol::named_states(protocol) {
+        let recording = Recording::new_ir(protocol.encode(&state)?);
+        commands.insert(
+            format!("{}{}", prefix, name),
+            base64::encode(recording.to_bytes()).into(),
+        );
+    }
+
+    storage.data.insert(device.into(), commands.into());
+    Ok(())
+}
+
+/// Decodes a base64 code learned by Home Assistant
+pub fn decode_code(protocol: &dyn Protocol, code: &str) -> anyhow::Result<ControlState> {
+    let recording = Recording::from_bytes(Bytes::from(base64::decode(code)?))?;
+    protocol.decode(&recording.pulses())
+}
+
+pub fn run(protocol: &dyn Protocol, command: HaStorageCommand) -> anyhow::Result<()> {
+    match command {
+        HaStorageCommand::Export {
+            device,
+            prefix,
+            mac,
+            storage,
+        } => {
+            let mut storage = match (storage, mac) {
+                (Some(path), _) => serde_json::from_reader(std::fs::File::open(path)?)?,
+                (None, Some(mac)) => Storage::new(&mac),
+                (None, None) => bail!("either --mac or --storage is required"),
+            };
+
+            export_states(protocol, &mut storage, &device, &prefix)?;
+            println!("{}", serde_json::to_string_pretty(&storage)?);
+        }
+        HaStorageCommand::Audit => {
+            let mut input = String::new();
+            io::stdin().read_to_string(&mut input)?;
+            let storage: Storage = serde_json::from_str(&input)?;
+
+            for (device, command, code) in storage.codes()? {
+                match decode_code(protocol, &code) {
+                    Ok(state) => println!("{}/{}: {:?}", device, command, state),
+                    Err(e) => println!("{}/{}: Error: {:#}", device, command, e),
+                }
+            }
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{lennox::Lennox, state::Mode};
+
+    #[test]
+    fn test_export() {
+        let lennox = Lennox::new();
+        let mut storage = Storage::new("34:EA:34:B4:3C:5D");
+        assert_eq!(storage.key, "broadlink_remote_34ea34b43c5d_codes");
+
+        export_states(&lennox, &mut storage, "bedroom", "ac_").unwrap();
+        let storage: Storage =
+            serde_json::from_str(&serde_json::to_string(&storage).unwrap()).unwrap();
+
+        let codes = storage.codes().unwrap();
+        assert_eq!(codes.len(), protocol::named_states(&lennox).len());
+
+        let (device, _, code) = codes
+            .iter()
+            .find(|(_, command, _)| command == "ac_cool_max_22")
+            .unwrap();
+        assert_eq!(device, "bedroom");
+        assert_eq!(decode_code(&lennox, code).unwrap().mode, Mode::Cool);
+    }
+
+    #[test]
+    fn test_audit() {
+        let off = include_str!("../captures/off.ir");
+        let off = base64::encode(hex::decode(off.trim()).unwrap());
+
+        // Codes learned by hand, including a toggle command and a code from another remote
+        let storage = serde_json::json!({
+            "version": 1,
+            "minor_version": 1,
+            "key": "broadlink_remote_34ea34b43c5d_codes",
+            "data": {
+                "heatpump": {
+                    "off": off,
+                    "toggle": [off, "JgAcAB0dHB44HhweGx4cHR06HB0cHhwdHB8bHhwADQUAAAAAAAAAAAAAAAA="],
+                }
+            }
+        });
+        let storage: Storage = serde_json::from_value(storage).unwrap();
+
+        let codes = storage.codes().unwrap();
+        let commands: Vec<_> = codes.iter().map(|(_, c, _)| c.as_str()).collect();
+        assert_eq!(commands, ["off", "toggle[0]", "toggle[1]"]);
+
+        let lennox = Lennox::new();
+        assert!(!decode_code(&lennox, &codes[0].2).unwrap().power);
+        assert!(decode_code(&lennox, &codes[1].2).is_ok());
+        assert!(decode_code(&lennox, &codes[2].2).is_err());
+    }
+}
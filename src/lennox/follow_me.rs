@@ -0,0 +1,188 @@
+use super::packet::{compute_checksum, EncodeError, Packet};
+use crate::state::ControlState;
+use bitfield::bitfield;
+use clap::Parser;
+
+/// Follow-me report, sent periodically by the remote so the unit regulates on the remote's own
+/// temperature sensor instead of the one inside the unit.
+#[derive(Debug, Clone, Copy, Parser)]
+pub struct FollowMe {
+    /// Whether follow-me is enabled. Sending a disabled report hands regulation back to the unit.
+    #[clap(short, long)]
+    pub enabled: bool,
+
+    /// Room temperature in Celsius, as measured by the remote
+    #[clap(short, long)]
+    pub room_temperature: u8,
+
+    #[clap(flatten)]
+    pub state: ControlState,
+}
+
+bitfield! {
+    pub struct FollowMePacket(u64);
+    impl Debug;
+    pub u8, cmd_type, set_cmd_type : 47, 40;
+    u64, state_raw, set_state_raw : 39, 24;
+    pub u8, follow_me_raw, set_follow_me_raw : 23, 22;
+    u8, ones, set_ones : 21, 16;
+    pub u8, room_temperature_raw, set_room_temperature_raw : 15, 8;
+    pub u8, checksum, set_checksum : 7, 0;
+}
+
+impl Clone for FollowMePacket {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl Copy for FollowMePacket {}
+
+impl FollowMePacket {
+    const FOLLOW_ME_ON: u8 = 0b11;
+    const FOLLOW_ME_OFF: u8 = 0b00;
+
+    // Like other Midea-family remotes, the sensor reading is offset by one
+    const ROOM_TEMPERATURE_OFFSET: u8 = 1;
+    const ROOM_TEMPERATURE_MAX: u8 = 37;
+
+    const ONES: u8 = 0b111111;
+    pub const CMD_TYPE: u8 = 0b10100100;
+
+    pub fn new() -> Self {
+        let mut p = FollowMePacket(0);
+        p.set_cmd_type(FollowMePacket::CMD_TYPE);
+        p.set_ones(FollowMePacket::ONES);
+        p
+    }
+
+    pub fn from_follow_me(follow_me: &FollowMe) -> Result<Self, EncodeError> {
+        let mut packet = Self::new();
+        packet.set_state(&follow_me.state)?;
+        packet.set_enabled(follow_me.enabled);
+        packet.set_room_temperature(follow_me.room_temperature)?;
+        packet.set_checksum(compute_checksum(packet.0));
+        Ok(packet)
+    }
+
+    pub fn to_follow_me(self) -> Result<FollowMe, EncodeError> {
+        if self.cmd_type() != FollowMePacket::CMD_TYPE || self.ones() != FollowMePacket::ONES {
+            return Err(EncodeError::UnexpectedFixedValues);
+        }
+
+        if compute_checksum(self.0) != self.checksum() {
+            return Err(EncodeError::ChecksumMismatch);
+        }
+
+        Ok(FollowMe {
+            enabled: self.enabled()?,
+            room_temperature: self.room_temperature(),
+            state: self.state()?,
+        })
+    }
+
+    /// The state fields share the layout of a regular state packet
+    fn state_packet(&self) -> Packet {
+        let mut packet = Packet::new();
+        packet.set_state_bits(self.state_raw());
+        packet
+    }
+
+    pub fn state(&self) -> Result<ControlState, EncodeError> {
+        self.state_packet().state()
+    }
+
+    pub fn set_state(&mut self, state: &ControlState) -> Result<(), EncodeError> {
+        let mut packet = Packet::new();
+        packet.set_state(state)?;
+        self.set_state_raw(packet.state_bits());
+        Ok(())
+    }
+
+    pub fn enabled(&self) -> Result<bool, EncodeError> {
+        match self.follow_me_raw() {
+            FollowMePacket::FOLLOW_ME_ON => Ok(true),
+            FollowMePacket::FOLLOW_ME_OFF => Ok(false),
+            _ => Err(EncodeError::UnexpectedFixedValues),
+        }
+    }
+
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.set_follow_me_raw(if enabled {
+            FollowMePacket::FOLLOW_ME_ON
+        } else {
+            FollowMePacket::FOLLOW_ME_OFF
+        });
+    }
+
+    /// Returns the room temperature in Celsius
+    pub fn room_temperature(&self) -> u8 {
+        self.room_temperature_raw()
+            .saturating_sub(FollowMePacket::ROOM_TEMPERATURE_OFFSET)
+    }
+
+    pub fn set_room_temperature(&mut self, temp: u8) -> Result<(), EncodeError> {
+        if temp > FollowMePacket::ROOM_TEMPERATURE_MAX {
+            return Err(EncodeError::RoomTemperatureOutOfRange);
+        }
+
+        self.set_room_temperature_raw(temp + FollowMePacket::ROOM_TEMPERATURE_OFFSET);
+        Ok(())
+    }
+}
+
+impl TryFrom<FollowMe> for FollowMePacket {
+    type Error = EncodeError;
+
+    fn try_from(follow_me: FollowMe) -> Result<Self, EncodeError> {
+        Self::from_follow_me(&follow_me)
+    }
+}
+
+impl TryFrom<FollowMePacket> for FollowMe {
+    type Error = EncodeError;
+
+    fn try_from(packet: FollowMePacket) -> Result<Self, EncodeError> {
+        packet.to_follow_me()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state::{Fan, Mode, Temperature};
+
+    #[test]
+    fn test_decode() {
+        let on = FollowMePacket(0xa4a34dff16bf).to_follow_me().unwrap();
+        assert!(on.enabled);
+        assert_eq!(on.room_temperature, 21);
+        assert!(on.state.power);
+        assert_eq!(on.state.mode, Mode::Heat);
+        assert_eq!(on.state.fan, Fan::Auto);
+        assert_eq!(on.state.temperature, Some(Temperature::Celsius(30)));
+
+        let off = FollowMePacket(0xa4a34d3f1600).to_follow_me().unwrap();
+        assert!(!off.enabled);
+        assert_eq!(off.room_temperature, 21);
+    }
+
+    #[test]
+    fn test_encode() {
+        for bits in [0xa4a34dff16bf, 0xa4a34d3f1600] {
+            let follow_me = FollowMePacket(bits).to_follow_me().unwrap();
+            let packet = FollowMePacket::from_follow_me(&follow_me).unwrap();
+            assert_eq!(packet.0, bits);
+        }
+    }
+
+    #[test]
+    fn test_room_temperature_range() {
+        let mut packet = FollowMePacket::new();
+        assert!(packet.set_room_temperature(37).is_ok());
+        assert!(matches!(
+            packet.set_room_temperature(38),
+            Err(EncodeError::RoomTemperatureOutOfRange)
+        ));
+    }
+}
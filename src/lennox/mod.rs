@@ -1,42 +1,175 @@
 pub mod phy;
-use clap::Parser;
 pub use phy::*;
+pub mod follow_me;
 pub mod packet;
+pub mod recovery;
+pub mod special;
+pub mod stream;
 
-// The complete state sent to the heat pump
-#[derive(Debug, Clone, Copy, Parser)]
-pub struct ControlState {
-    /// Power state
-    #[clap(short, long)]
-    pub power: bool,
+use std::{ops::RangeInclusive, time::Duration};
 
-    /// Operating mode
-    #[clap(short, long)]
-    pub mode: Mode,
+use anyhow::Context;
 
-    // Current set temperature in Celsius, or None if it is only in fan mode
-    #[clap(short, long)]
-    pub temperature: Option<u8>,
+use crate::{
+    protocol::Protocol,
+    pwm::{Codec, CodecConfig},
+    state::{ControlState, Fan, Mode},
+};
+use follow_me::{FollowMe, FollowMePacket};
+use packet::{EncodeError, Packet};
+use recovery::{Correction, Frame};
+use special::{SpecialFunction, SpecialPacket};
+use stream::StreamDecoder;
 
-    // Fan speed setting
-    #[clap(short, long)]
-    pub fan: Fan,
+/// Any command sent by the remote, identified by the packet's command type byte
+#[derive(Debug, Clone, Copy)]
+pub enum Command {
+    State(ControlState),
+    Special(SpecialFunction),
+    FollowMe(FollowMe),
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, strum::EnumString, strum::EnumIter, strum::AsRefStr)]
-pub enum Fan {
-    Min,
-    Medium,
-    Max,
-    Auto,
-    Zero,
+impl Command {
+    pub fn decode(bits: u64) -> Result<Self, EncodeError> {
+        let cmd_type = Packet(bits).cmd_type();
+        match cmd_type {
+            Packet::CMD_TYPE => Ok(Command::State(Packet(bits).to_control_state()?)),
+            SpecialPacket::CMD_TYPE => Ok(Command::Special(SpecialPacket(bits).to_function()?)),
+            FollowMePacket::CMD_TYPE => Ok(Command::FollowMe(FollowMePacket(bits).to_follow_me()?)),
+            _ => Err(EncodeError::UnknownCommandType(cmd_type)),
+        }
+    }
+
+    pub fn encode(&self) -> Result<u64, EncodeError> {
+        Ok(match self {
+            Command::State(state) => Packet::from_control_state(state)?.0,
+            Command::Special(function) => SpecialPacket::from_function(*function).0,
+            Command::FollowMe(follow_me) => FollowMePacket::from_follow_me(follow_me)?.0,
+        })
+    }
+}
+
+/// Lennox heat pumps, driven by the RG57A6/BGEFU1 remote
+pub struct Lennox {
+    phy: Phy,
+    recover: bool,
+}
+
+impl Lennox {
+    pub fn new() -> Self {
+        Self {
+            phy: Phy::new(),
+            recover: false,
+        }
+    }
+
+    /// Creates a push-based decoder for continuous captures, recovering damaged transmissions if
+    /// recovery is enabled
+    pub fn stream(&self) -> StreamDecoder<'_> {
+        self.phy.stream().with_recovery(self.recover)
+    }
+
+    /// Encodes any command the remote can send into on-off pulse durations
+    pub fn encode_command(&self, command: &Command) -> anyhow::Result<Vec<Duration>> {
+        Ok(self.phy.encode(command.encode()?)?)
+    }
+
+    /// Decodes a message, falling back to error recovery if it's enabled. Returns the bits that
+    /// had to be corrected along with the message.
+    fn decode_message(&self, pulses: &[Duration]) -> anyhow::Result<(u64, Vec<Correction>)> {
+        match self.phy.decode(pulses.iter().copied()) {
+            Ok(msg) => Ok((msg, Vec::new())),
+            Err(e) if self.recover => {
+                let recovered = self
+                    .phy
+                    .decode_recover(pulses)
+                    .with_context(|| format!("{}, and recovery failed", e))?;
+                Ok((recovered.bits, recovered.corrected))
+            }
+            Err(e) => Err(e.into()),
+        }
+    }
+}
+
+impl Protocol for Lennox {
+    fn name(&self) -> &'static str {
+        "lennox"
+    }
+
+    fn manufacturer(&self) -> &'static str {
+        "Lennox"
+    }
+
+    fn models(&self) -> &'static [&'static str] {
+        &["MWMA018S4-2P", "RG57A6/BGEFU1"]
+    }
+
+    fn modes(&self) -> &'static [Mode] {
+        &[Mode::Auto, Mode::Cool, Mode::Dry, Mode::Heat, Mode::Fan]
+    }
+
+    fn fans(&self) -> &'static [Fan] {
+        // Fan::Zero is only reported by the unit in modes where it controls the fan by itself
+        &[Fan::Min, Fan::Medium, Fan::Max, Fan::Auto]
+    }
+
+    fn temperature_range(&self) -> RangeInclusive<u8> {
+        17..=30
+    }
+
+    fn supports_sleep(&self) -> bool {
+        true
+    }
+
+    fn encode(&self, state: &ControlState) -> anyhow::Result<Vec<Duration>> {
+        self.encode_command(&Command::State(*state))
+    }
+
+    fn decode(&self, pulses: &[Duration]) -> anyhow::Result<ControlState> {
+        let (bits, _) = self.decode_message(pulses)?;
+        Ok(Packet(bits).to_control_state()?)
+    }
+
+    fn describe(&self, pulses: &[Duration]) -> anyhow::Result<String> {
+        let (msg, corrected) = self.decode_message(pulses)?;
+        let command = Command::decode(msg).with_context(|| format!("Recv: {:x} {:b}", msg, msg))?;
+
+        let mut description = format!("Recv: {:x} {:b}\nDecode: {:?}", msg, msg, command);
+        if !corrected.is_empty() {
+            description += &format!("\nCorrected: {}", describe_corrections(&corrected));
+        }
+        Ok(description)
+    }
+
+    fn confidence(&self, pulses: &[Duration]) -> f32 {
+        self.phy.confidence(pulses.iter().copied())
+    }
+
+    fn calibrate(&self, pulses: &[Duration]) -> anyhow::Result<serde_json::Value> {
+        let codec = self.phy.codec().calibrate(pulses.iter().copied());
+        Ok(serde_json::to_value(codec.config())?)
+    }
+
+    fn set_timings(&mut self, timings: serde_json::Value) -> anyhow::Result<()> {
+        let config: CodecConfig<PulseType> = serde_json::from_value(timings)?;
+        self.phy = Phy::with_codec(Codec::from_config(&config));
+        Ok(())
+    }
+
+    fn set_recovery(&mut self, enabled: bool) -> anyhow::Result<()> {
+        self.recover = enabled;
+        Ok(())
+    }
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, strum::EnumString, strum::EnumIter, strum::AsRefStr)]
-pub enum Mode {
-    Auto,
-    Cool,
-    Dry,
-    Heat,
-    Fan,
+/// Lists the bits corrected by recovery, e.g. "bit 10 (first frame)"
+pub fn describe_corrections(corrected: &[Correction]) -> String {
+    corrected
+        .iter()
+        .map(|c| match c.frame {
+            Frame::First => format!("bit {} (first frame)", c.bit),
+            Frame::Repeat => format!("bit {} (repeat frame)", c.bit),
+        })
+        .collect::<Vec<_>>()
+        .join(", ")
 }
@@ -1,11 +1,17 @@
-use super::{ControlState, Fan, Mode};
+use crate::state::{ControlState, Fan, Mode, Temperature, TemperatureUnit};
 use bitfield::bitfield;
 use thiserror::Error;
 
 #[derive(Error, Clone, Copy, Debug)]
 pub enum EncodeError {
-    #[error("Temperature out of range. Must be between 17C and 30C")]
-    TemperatureOutOfRange,
+    #[error("Temperature {0} out of range. Must be between 17C and 30C")]
+    TemperatureOutOfRange(Temperature),
+
+    #[error("Temperature missing, only fan mode has no set point")]
+    TemperatureMissing,
+
+    #[error("{0} isn't supported yet, no capture from the remote confirms its encoding")]
+    Unverified(&'static str),
 
     #[error("Mode value wasn't recognized")]
     ModeOutOfRange(u8),
@@ -13,6 +19,15 @@ pub enum EncodeError {
     #[error("Fan value wasn't recognized")]
     FanOutOfRange(u8),
 
+    #[error("Special function value wasn't recognized")]
+    FunctionOutOfRange(u8),
+
+    #[error("Room temperature out of range. Must be between 0C and 37C")]
+    RoomTemperatureOutOfRange,
+
+    #[error("Unknown command type")]
+    UnknownCommandType(u8),
+
     #[error("Unexpected fixed value in packet.")]
     UnexpectedFixedValues,
     #[error("Checksum mismatch")]
@@ -27,7 +42,9 @@ bitfield! {
     pub sleep, set_sleep : 38;
     pub u8, fan_raw, set_fan_raw: 37, 35;
     pub u8, mode_raw, set_mode_raw: 34, 32;
-    u8, unknown, set_unknown : 31, 29;
+    u8, unknown, set_unknown : 31, 30;
+    pub fahrenheit, set_fahrenheit : 29;
+    pub u64, state_bits, set_state_bits : 39, 24;
     pub u8, temperature_raw, set_temperature_raw: 28, 24;
     u16, ones, set_ones : 23, 8;
     pub u8, checksum, set_checksum : 7, 0;
@@ -35,7 +52,7 @@ bitfield! {
 
 impl Clone for Packet {
     fn clone(&self) -> Self {
-        Packet(self.0)
+        *self
     }
 }
 
@@ -58,9 +75,11 @@ impl Packet {
     const FAN_MAX: u8 = 0b011;
     const FAN_AUTO: u8 = 0b100;
 
+    // Every capture has bits 23..8 set. The Midea family keeps its on/off timers there, see
+    // notes.txt, but no capture with a timer set confirms the layout.
     const ONES: u16 = 0xFFFF;
-    const UNKNOWN: u8 = 0b010;
-    const CMD_TYPE: u8 = 0b10100001;
+    const UNKNOWN: u8 = 0b01;
+    pub const CMD_TYPE: u8 = 0b10100001;
 
     pub fn new() -> Self {
         let mut p = Packet(0);
@@ -72,10 +91,7 @@ impl Packet {
 
     pub fn from_control_state(state: &ControlState) -> Result<Self, EncodeError> {
         let mut packet = Self::new();
-        packet.set_temperature(state.temperature)?;
-        packet.set_power(state.power);
-        packet.set_mode(state.mode);
-        packet.set_fan(state.fan);
+        packet.set_state(state)?;
         packet.apply_checksum();
         Ok(packet)
     }
@@ -83,42 +99,78 @@ impl Packet {
     pub fn to_control_state(self) -> Result<ControlState, EncodeError> {
         if self.cmd_type() != Packet::CMD_TYPE
             || self.unknown() != Packet::UNKNOWN
-            || self.ones() != Packet::ONES
         {
             return Err(EncodeError::UnexpectedFixedValues);
         }
+        if self.ones() != Packet::ONES {
+            return Err(EncodeError::Unverified("on/off timers"));
+        }
 
         if !self.validate_checksum() {
             return Err(EncodeError::ChecksumMismatch);
         }
 
+        self.state()
+    }
+
+    /// Reads the state fields, without validating the fixed values or the checksum
+    pub(crate) fn state(&self) -> Result<ControlState, EncodeError> {
         Ok(ControlState {
             power: self.power(),
             mode: self.mode()?,
             fan: self.fan()?,
-            temperature: self.temperature(),
+            temperature: self.temperature()?,
+            sleep: self.sleep(),
+            off_timer: None,
+            on_timer: None,
         })
     }
 
-    // Returns the temperature in Celsius, or None if it is only in fan mode
-    pub fn temperature(&self) -> Option<u8> {
-        if self.temperature_raw() == Packet::TEMP_NONE {
-            None
-        } else {
-            Some(self.temperature_raw() + 17)
+    /// Writes the state fields, leaving the checksum untouched
+    pub(crate) fn set_state(&mut self, state: &ControlState) -> Result<(), EncodeError> {
+        if state.temperature.is_none() && state.mode != Mode::Fan {
+            return Err(EncodeError::TemperatureMissing);
         }
+        self.set_temperature(state.temperature)?;
+        self.set_power(state.power);
+        self.set_mode(state.mode);
+        self.set_fan(state.fan);
+        self.set_sleep(state.sleep);
+        if state.off_timer.is_some() || state.on_timer.is_some() {
+            return Err(EncodeError::Unverified("on/off timers"));
+        }
+        Ok(())
     }
 
-    pub fn set_temperature(&mut self, temp: Option<u8>) -> Result<(), EncodeError> {
+    /// Returns the set temperature, or None in fan mode, whose captures can carry leftover values
+    /// that aren't valid set points. Other modes must carry a valid set point.
+    pub fn temperature(&self) -> Result<Option<Temperature>, EncodeError> {
+        // Bit 29 is assumed to flag Fahrenheit from the Midea family, no capture confirms it
+        if self.fahrenheit() {
+            return Err(EncodeError::Unverified("Fahrenheit mode"));
+        }
+
+        let temp =
+            Temperature::Celsius(self.temperature_raw() + TemperatureUnit::Celsius.range().start());
+        match self.mode()? {
+            Mode::Fan => Ok(Some(temp).filter(Temperature::is_valid)),
+            _ if temp.is_valid() => Ok(Some(temp)),
+            _ => Err(EncodeError::TemperatureOutOfRange(temp)),
+        }
+    }
+
+    pub fn set_temperature(&mut self, temp: Option<Temperature>) -> Result<(), EncodeError> {
         let temp = match temp {
-            Some(temp) if !(17..=30).contains(&temp) => {
-                return Err(EncodeError::TemperatureOutOfRange)
+            Some(temp) if !temp.is_valid() => return Err(EncodeError::TemperatureOutOfRange(temp)),
+            Some(Temperature::Fahrenheit(_)) => {
+                return Err(EncodeError::Unverified("Fahrenheit mode"))
             }
-            Some(temp) => temp - 17,
+            Some(temp) => temp.value() - TemperatureUnit::Celsius.range().start(),
             None => Packet::TEMP_NONE,
         };
 
         self.set_temperature_raw(temp);
+        self.set_fahrenheit(false);
         Ok(())
     }
 
@@ -165,15 +217,7 @@ impl Packet {
     }
 
     fn compute_checksum(&self) -> u8 {
-        // Adapted from https://github.com/efficks/lennoxir/blob/master/common.py
-        let mut packet = Packet(self.0);
-        packet.set_checksum(0);
-
-        let mut sum: u8 = 0x00;
-        for &v in packet.0.to_ne_bytes().iter() {
-            sum = sum.wrapping_add(rev(v) as _);
-        }
-        rev(u8::MAX - sum + 1)
+        compute_checksum(self.0)
     }
 
     fn apply_checksum(&mut self) {
@@ -201,6 +245,19 @@ impl TryFrom<Packet> for ControlState {
     }
 }
 
+/// Computes the checksum byte of a 48-bit frame, ignoring its current checksum byte.
+/// The same algorithm is used by every command type sent by the remote.
+pub(crate) fn compute_checksum(bits: u64) -> u8 {
+    // Adapted from https://github.com/efficks/lennoxir/blob/master/common.py
+    let bits = bits & !0xFF;
+
+    let mut sum: u8 = 0x00;
+    for &v in bits.to_ne_bytes().iter() {
+        sum = sum.wrapping_add(rev(v) as _);
+    }
+    rev((u8::MAX - sum).wrapping_add(1))
+}
+
 fn rev(input: u8) -> u8 {
     let mut output: u8 = 0;
     for i in 0..8 {
@@ -220,18 +277,41 @@ mod tests {
         let off = 0xa12347ffffeb;
         let on = 0xa1a347ffff6b;
 
-        assert_eq!(Packet(off).power(), false);
-        assert_eq!(Packet(on).power(), true);
+        assert!(!Packet(off).power());
+        assert!(Packet(on).power());
         assert_eq!(Packet(on).mode().unwrap(), Mode::Heat);
         assert_eq!(Packet(on).fan().unwrap(), Fan::Auto);
 
         // Temperatures
-        assert_eq!(Packet(0xa1a348ffff65).temperature(), Some(25));
-        assert_eq!(Packet(0xa1a349ffff64).temperature(), Some(26));
-        assert_eq!(Packet(0xa1a34affff66).temperature(), Some(27));
-        assert_eq!(Packet(0xa1a34bffff67).temperature(), Some(28));
-        assert_eq!(Packet(0xa1a34cffff61).temperature(), Some(29));
-        assert_eq!(Packet(0xa1a34dffff60).temperature(), Some(30));
+        assert_eq!(
+            Packet(0xa1a348ffff65).temperature().unwrap(),
+            Some(Temperature::Celsius(25))
+        );
+        assert_eq!(
+            Packet(0xa1a349ffff64).temperature().unwrap(),
+            Some(Temperature::Celsius(26))
+        );
+        assert_eq!(
+            Packet(0xa1a34affff66).temperature().unwrap(),
+            Some(Temperature::Celsius(27))
+        );
+        assert_eq!(
+            Packet(0xa1a34bffff67).temperature().unwrap(),
+            Some(Temperature::Celsius(28))
+        );
+        assert_eq!(
+            Packet(0xa1a34cffff61).temperature().unwrap(),
+            Some(Temperature::Celsius(29))
+        );
+        assert_eq!(
+            Packet(0xa1a34dffff60).temperature().unwrap(),
+            Some(Temperature::Celsius(30))
+        );
+
+        // Sleep
+        assert!(Packet(0xa1e34dffff20).sleep());
+        assert!(!Packet(0xa1a34dffff60).sleep());
+        assert!(Packet(0xa1e34dffff20).to_control_state().unwrap().sleep);
 
         dbg!(Packet(0xa1a34dffff60));
         let state: ControlState = Packet(0xa1a34dffff60).to_control_state().unwrap();
@@ -240,7 +320,7 @@ mod tests {
 
     #[test]
     fn test_encode() {
-        let packets = [Packet(0xa1a348ffff65)];
+        let packets = [Packet(0xa1a348ffff65), Packet(0xa1e34dffff20)];
 
         for packet in packets.iter() {
             let state: ControlState = packet.to_control_state().unwrap();
@@ -249,6 +329,85 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_fahrenheit() {
+        // Bit 29 set, as the Midea family does in Fahrenheit mode. Without a capture from this
+        // remote, such frames are rejected rather than decoded on a guess.
+        let mut packet = Packet(0xa1a348ffff65);
+        packet.set_fahrenheit(true);
+        packet.apply_checksum();
+        assert!(matches!(
+            packet.to_control_state(),
+            Err(EncodeError::Unverified(_))
+        ));
+
+        let base = Packet(0xa1a348ffff65).to_control_state().unwrap();
+        for (temp, valid) in [
+            (Temperature::Fahrenheit(77), true),
+            (Temperature::Fahrenheit(87), false),
+            (Temperature::Celsius(16), false),
+        ] {
+            let state = ControlState {
+                temperature: Some(temp),
+                ..base
+            };
+            match Packet::from_control_state(&state) {
+                Err(EncodeError::Unverified(_)) => assert!(valid),
+                Err(EncodeError::TemperatureOutOfRange(t)) => assert!(!valid && t == temp),
+                result => panic!("unexpected result: {:?}", result),
+            }
+        }
+    }
+
+    #[test]
+    fn test_fan_mode_temperature() {
+        // Fan mode capture with a leftover temperature value that isn't a set point
+        let state = Packet(0xa1a45effff7b).to_control_state().unwrap();
+        assert_eq!(state.mode, Mode::Fan);
+        assert_eq!(state.temperature, None);
+
+        // The same value in heat mode is a corrupted set point, not a missing one
+        let mut packet = Packet(0xa1a45effff7b);
+        packet.set_mode(Mode::Heat);
+        packet.apply_checksum();
+        assert!(matches!(
+            packet.to_control_state(),
+            Err(EncodeError::TemperatureOutOfRange(_))
+        ));
+
+        let state = ControlState {
+            temperature: None,
+            ..Packet(0xa1a348ffff65).to_control_state().unwrap()
+        };
+        assert!(matches!(
+            Packet::from_control_state(&state),
+            Err(EncodeError::TemperatureMissing)
+        ));
+    }
+
+    #[test]
+    fn test_timers() {
+        // Timers aren't encoded until a capture confirms their layout, and frames that may carry
+        // one are rejected rather than decoded without it
+        let base = Packet(0xa1a348ffff65).to_control_state().unwrap();
+        let state = ControlState {
+            off_timer: Some(30),
+            ..base
+        };
+        assert!(matches!(
+            Packet::from_control_state(&state),
+            Err(EncodeError::Unverified(_))
+        ));
+
+        let mut packet = Packet(0xa1a348ffff65);
+        packet.set_ones(0xFF7F);
+        packet.apply_checksum();
+        assert!(matches!(
+            packet.to_control_state(),
+            Err(EncodeError::Unverified(_))
+        ));
+    }
+
     #[test]
     pub fn test_checksum() {
         let known_packets: &[u64; 7] = &[
@@ -1,9 +1,10 @@
 use crate::pwm::{Codec, CodecError, Rule};
 
+use serde::{Deserialize, Serialize};
 use std::time::Duration;
 use thiserror::Error;
 
-#[derive(Clone, Copy, Debug, Eq, PartialEq, Hash)]
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Hash, Serialize, Deserialize)]
 pub enum PulseType {
     Short,
     Long,
@@ -20,7 +21,8 @@ pub enum PhyError {
     DecodeError(#[from] DecodeError),
 }
 
-const PREAMBLE: (PulseType, PulseType) = (PulseType::FourThousand, PulseType::FourThousand);
+pub(crate) const PREAMBLE: (PulseType, PulseType) =
+    (PulseType::FourThousand, PulseType::FourThousand);
 
 pub struct Phy {
     codec: Codec<PulseType>,
@@ -47,6 +49,15 @@ impl Phy {
         Self { codec }
     }
 
+    /// Uses custom pulse timings, e.g. calibrated from captures of a specific remote
+    pub fn with_codec(codec: Codec<PulseType>) -> Self {
+        Self { codec }
+    }
+
+    pub fn codec(&self) -> &Codec<PulseType> {
+        &self.codec
+    }
+
     pub fn encode(&self, bits: u64) -> Result<Vec<Duration>, PhyError> {
         let pulses = self.encode_pulses(bits);
         Ok(self.codec.encode(pulses.into_iter())?)
@@ -57,6 +68,11 @@ impl Phy {
         Ok(self.decode_pulses(pulses.into_iter())?)
     }
 
+    /// How closely the pulse timings match the nominal ones, from 0.0 to 1.0
+    pub fn confidence(&self, pulses: impl Iterator<Item = Duration>) -> f32 {
+        self.codec.confidence(pulses)
+    }
+
     pub fn encode_pulses(&self, bits: u64) -> Vec<PulseType> {
         let mut pulses = Vec::with_capacity(2 * (48 * 2 + 2));
 
@@ -159,11 +175,13 @@ pub enum DecodeError {
     RepeatMismatch,
     #[error("truncated message")]
     TruncatedMessage,
+    #[error("no valid message could be recovered")]
+    Unrecoverable,
 }
 
 #[cfg(test)]
 mod test {
-    use crate::broadlink::{Recording, Transport};
+    use crate::broadlink::Recording;
 
     use super::*;
 
@@ -184,23 +202,38 @@ mod test {
         let message = Recording::from_bytes(hex::decode(off).unwrap().into()).unwrap();
 
         let phy = Phy::new();
-        let msg = phy.decode(message.pulses.into_iter()).unwrap();
+        let msg = phy.decode(message.pulses().into_iter()).unwrap();
         assert_eq!(msg, MSG);
 
         /////////////
 
         let pulses = phy.encode(MSG).unwrap();
-        let recording = Recording {
-            repeat_count: 0,
-            transport: Transport::Ir,
-            pulses,
-        };
+        let recording = Recording::new_ir(pulses);
         let recording_bytes = recording.to_bytes();
 
         /////////////
         let recording = Recording::from_bytes(recording_bytes).unwrap();
         let phy = Phy::new();
-        let msg = phy.decode(recording.pulses.into_iter()).unwrap();
+        let msg = phy.decode(recording.pulses().into_iter()).unwrap();
         assert_eq!(msg, MSG);
     }
+
+    #[test]
+    fn test_calibrate() {
+        const MSG: u64 = 0xa12347ffffeb;
+        let off = include_str!("../../captures/off.ir");
+        let message = Recording::from_bytes(hex::decode(off).unwrap().into()).unwrap();
+
+        let phy = Phy::with_codec(Phy::new().codec().calibrate(message.pulses().into_iter()));
+        assert!(
+            phy.confidence(message.pulses().into_iter())
+                > Phy::new().confidence(message.pulses().into_iter())
+        );
+        assert_eq!(phy.decode(message.pulses().into_iter()).unwrap(), MSG);
+
+        // Encoding uses the calibrated timings
+        let short = phy.codec().encode_pulse(PulseType::Short).unwrap();
+        assert_eq!(phy.encode(MSG).unwrap()[2], short);
+        assert_ne!(short, Duration::from_micros(500));
+    }
 }
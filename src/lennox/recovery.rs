@@ -0,0 +1,234 @@
+use std::time::Duration;
+
+use crate::pwm::CodecError;
+
+use super::{
+    packet::compute_checksum,
+    phy::{DecodeError, Phy, PhyError, PulseType, PREAMBLE},
+};
+
+/// Which of the two frames of a transmission a bit was read from
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Frame {
+    First,
+    /// The complemented repeat of the first frame
+    Repeat,
+}
+
+/// A bit whose value was overridden during recovery
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Correction {
+    /// Bit position in the packet, 47 being the most significant bit
+    pub bit: u8,
+    /// The frame in which the bit was wrong
+    pub frame: Frame,
+}
+
+/// A message reconstructed from a damaged transmission
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Recovered {
+    pub bits: u64,
+    pub corrected: Vec<Correction>,
+}
+
+/// A bit read from the length of its space, with how sure we are of it, from 0.0 to 1.0
+#[derive(Debug, Clone, Copy)]
+struct SoftBit {
+    value: bool,
+    confidence: f32,
+}
+
+impl Phy {
+    const FRAME_BITS: usize = 48;
+    /// Preamble, then a mark and a space per bit
+    pub(super) const FRAME_PULSES: usize = 2 + 2 * Phy::FRAME_BITS;
+    /// Upper bound on the number of bits the two frames can disagree on, every combination of
+    /// them is checked against the checksum
+    const MAX_DISAGREEMENTS: usize = 12;
+
+    /// Decodes a transmission, correcting bits that don't match between the first frame and its
+    /// complemented repeat. Each disagreement is settled by the pulse timings, and the checksum
+    /// picks between the remaining candidates when the timings get it wrong. Pulses outside of
+    /// the codec's tolerance are read as the nearest pulse type instead of failing the decode.
+    pub fn decode_recover(&self, pulses: &[Duration]) -> Result<Recovered, PhyError> {
+        let first_start = self.find_preamble(pulses, 0)?;
+        let first = self.soft_bits(pulses, first_start)?;
+        let repeat_start = self.find_preamble(pulses, first_start + Phy::FRAME_PULSES)?;
+        let repeat = self.soft_bits(pulses, repeat_start)?;
+
+        // The repeat frame is inverted, so agreeing bits have different values
+        let disagreements: Vec<usize> = (0..Phy::FRAME_BITS)
+            .filter(|&i| first[i].value == repeat[i].value)
+            .collect();
+
+        if disagreements.len() > Phy::MAX_DISAGREEMENTS {
+            return Err(DecodeError::Unrecoverable.into());
+        }
+
+        let bits_from_first = first
+            .iter()
+            .fold(0u64, |bits, bit| (bits << 1) | bit.value as u64);
+
+        // Try every way of settling the disagreements. A set bit in `choice` means the repeat
+        // frame is trusted at that position. Keep the valid candidate overriding the least
+        // confident bits.
+        let mut best: Option<(f32, u64, u32)> = None;
+        for choice in 0u32..(1 << disagreements.len()) {
+            let mut bits = bits_from_first;
+            let mut cost = 0.0;
+            for (n, &i) in disagreements.iter().enumerate() {
+                let mask = 1 << (Phy::FRAME_BITS - 1 - i);
+                if choice & (1 << n) != 0 {
+                    bits ^= mask;
+                    cost += first[i].confidence;
+                } else {
+                    cost += repeat[i].confidence;
+                }
+            }
+
+            if compute_checksum(bits) != bits as u8 {
+                continue;
+            }
+
+            if best.is_none_or(|(best_cost, _, _)| cost < best_cost) {
+                best = Some((cost, bits, choice));
+            }
+        }
+
+        let (_, bits, choice) = best.ok_or(DecodeError::Unrecoverable)?;
+        let corrected = disagreements
+            .iter()
+            .enumerate()
+            .map(|(n, &i)| Correction {
+                bit: (Phy::FRAME_BITS - 1 - i) as u8,
+                frame: if choice & (1 << n) != 0 {
+                    Frame::First
+                } else {
+                    Frame::Repeat
+                },
+            })
+            .collect();
+
+        Ok(Recovered { bits, corrected })
+    }
+
+    /// Index of the first preamble at or after `start`
+    fn find_preamble(&self, pulses: &[Duration], start: usize) -> Result<usize, PhyError> {
+        let codec = self.codec();
+        (start..pulses.len().saturating_sub(1))
+            .find(|&i| {
+                codec.nearest_pulse(pulses[i]) == Some(PREAMBLE.0)
+                    && codec.nearest_pulse(pulses[i + 1]) == Some(PREAMBLE.1)
+            })
+            .ok_or_else(|| DecodeError::TruncatedMessage.into())
+    }
+
+    /// Reads the bits of the frame starting at `start` from the length of their spaces
+    fn soft_bits(&self, pulses: &[Duration], start: usize) -> Result<Vec<SoftBit>, PhyError> {
+        let frame = pulses
+            .get(start + 2..start + Phy::FRAME_PULSES)
+            .ok_or(DecodeError::TruncatedMessage)?;
+
+        let nominal = |pulse| {
+            self.codec()
+                .encode_pulse(pulse)
+                .ok_or(CodecError::InvalidPulse(pulse))
+        };
+        let (short, long) = (
+            nominal(PulseType::Short)?.as_secs_f32(),
+            nominal(PulseType::Long)?.as_secs_f32(),
+        );
+
+        Ok(frame
+            .chunks(2)
+            .map(|pair| {
+                // Distances on a log scale, so that a pulse twice too long is as far off as one
+                // twice too short
+                let space = pair[1].as_secs_f32().max(f32::MIN_POSITIVE);
+                let to_short = (space / short).ln().abs();
+                let to_long = (space / long).ln().abs();
+                SoftBit {
+                    value: to_long < to_short,
+                    confidence: (to_short - to_long).abs() / (to_short + to_long),
+                }
+            })
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const MSG: u64 = 0xa12347ffffeb;
+
+    /// Index of the space carrying a given bit
+    fn space(frame: Frame, bit: u8) -> usize {
+        let frame_start = match frame {
+            Frame::First => 0,
+            Frame::Repeat => Phy::FRAME_PULSES + 2,
+        };
+        frame_start + 2 + 2 * (47 - bit as usize) + 1
+    }
+
+    #[test]
+    fn test_clean() {
+        let phy = Phy::new();
+        let pulses = phy.encode(MSG).unwrap();
+        let recovered = phy.decode_recover(&pulses).unwrap();
+        assert_eq!(recovered.bits, MSG);
+        assert!(recovered.corrected.is_empty());
+    }
+
+    #[test]
+    fn test_single_error() {
+        let phy = Phy::new();
+        // Bit 10 is a 1, and a 0 in the repeat frame. Damage its space until it reads as the
+        // opposite value, while staying ambiguous.
+        for (frame, damaged) in [(Frame::First, 850), (Frame::Repeat, 1000)] {
+            let mut pulses = phy.encode(MSG).unwrap();
+            pulses[space(frame, 10)] = Duration::from_micros(damaged);
+            assert!(phy.decode(pulses.iter().copied()).is_err());
+
+            let recovered = phy.decode_recover(&pulses).unwrap();
+            assert_eq!(recovered.bits, MSG);
+            assert_eq!(recovered.corrected, vec![Correction { bit: 10, frame }]);
+        }
+    }
+
+    #[test]
+    fn test_checksum_resolves() {
+        // The damaged space looks like a clean 0, but the repeat frame is less sure of itself.
+        // Timings alone would pick the wrong bit, the checksum rules it out.
+        let phy = Phy::new();
+        let mut pulses = phy.encode(MSG).unwrap();
+        pulses[space(Frame::First, 10)] = Duration::from_micros(500);
+        pulses[space(Frame::Repeat, 10)] = Duration::from_micros(750);
+
+        let recovered = phy.decode_recover(&pulses).unwrap();
+        assert_eq!(recovered.bits, MSG);
+        assert_eq!(
+            recovered.corrected,
+            vec![Correction {
+                bit: 10,
+                frame: Frame::First
+            }]
+        );
+    }
+
+    #[test]
+    fn test_unrecoverable() {
+        let phy = Phy::new();
+        let mut pulses = phy.encode(MSG).unwrap();
+        for bit in 0..16 {
+            let flipped = if MSG & (1 << bit) != 0 { 500 } else { 1500 };
+            pulses[space(Frame::First, bit)] = Duration::from_micros(flipped);
+        }
+        assert!(matches!(
+            phy.decode_recover(&pulses),
+            Err(PhyError::DecodeError(DecodeError::Unrecoverable))
+        ));
+
+        assert!(phy.decode_recover(&pulses[..150]).is_err());
+    }
+}
@@ -0,0 +1,151 @@
+use super::packet::{compute_checksum, EncodeError};
+use bitfield::bitfield;
+
+/// Functions triggered by the remote's dedicated buttons. Unlike state packets, these are
+/// toggles and don't carry the rest of the unit's state.
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, Hash, strum::EnumString, strum::EnumIter, strum::AsRefStr,
+)]
+pub enum SpecialFunction {
+    Led,
+    Swing,
+    Direct,
+    SilenceOn,
+    SilenceOff,
+    Turbo,
+}
+
+bitfield! {
+    pub struct SpecialPacket(u64);
+    impl Debug;
+    pub u8, cmd_type, set_cmd_type : 47, 40;
+    pub u8, function_raw, set_function_raw : 39, 32;
+    u32, ones, set_ones : 31, 8;
+    pub u8, checksum, set_checksum : 7, 0;
+}
+
+impl Clone for SpecialPacket {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl Copy for SpecialPacket {}
+
+impl SpecialPacket {
+    // Functions
+    const FUNCTION_LED: u8 = 0x08;
+    const FUNCTION_SWING: u8 = 0x02;
+    const FUNCTION_DIRECT: u8 = 0x01;
+    const FUNCTION_SILENCE_ON: u8 = 0x12;
+    const FUNCTION_SILENCE_OFF: u8 = 0x13;
+    const FUNCTION_TURBO: u8 = 0x09;
+
+    const ONES: u32 = 0xFF_FFFF;
+    pub const CMD_TYPE: u8 = 0b10100010;
+
+    pub fn new() -> Self {
+        let mut p = SpecialPacket(0);
+        p.set_cmd_type(SpecialPacket::CMD_TYPE);
+        p.set_ones(SpecialPacket::ONES);
+        p
+    }
+
+    pub fn from_function(function: SpecialFunction) -> Self {
+        let mut packet = Self::new();
+        packet.set_function(function);
+        packet.set_checksum(compute_checksum(packet.0));
+        packet
+    }
+
+    pub fn to_function(self) -> Result<SpecialFunction, EncodeError> {
+        if self.cmd_type() != SpecialPacket::CMD_TYPE || self.ones() != SpecialPacket::ONES {
+            return Err(EncodeError::UnexpectedFixedValues);
+        }
+
+        if compute_checksum(self.0) != self.checksum() {
+            return Err(EncodeError::ChecksumMismatch);
+        }
+
+        self.function()
+    }
+
+    pub fn function(&self) -> Result<SpecialFunction, EncodeError> {
+        Ok(match self.function_raw() {
+            SpecialPacket::FUNCTION_LED => SpecialFunction::Led,
+            SpecialPacket::FUNCTION_SWING => SpecialFunction::Swing,
+            SpecialPacket::FUNCTION_DIRECT => SpecialFunction::Direct,
+            SpecialPacket::FUNCTION_SILENCE_ON => SpecialFunction::SilenceOn,
+            SpecialPacket::FUNCTION_SILENCE_OFF => SpecialFunction::SilenceOff,
+            SpecialPacket::FUNCTION_TURBO => SpecialFunction::Turbo,
+            _ => return Err(EncodeError::FunctionOutOfRange(self.function_raw())),
+        })
+    }
+
+    pub fn set_function(&mut self, function: SpecialFunction) {
+        self.set_function_raw(match function {
+            SpecialFunction::Led => SpecialPacket::FUNCTION_LED,
+            SpecialFunction::Swing => SpecialPacket::FUNCTION_SWING,
+            SpecialFunction::Direct => SpecialPacket::FUNCTION_DIRECT,
+            SpecialFunction::SilenceOn => SpecialPacket::FUNCTION_SILENCE_ON,
+            SpecialFunction::SilenceOff => SpecialPacket::FUNCTION_SILENCE_OFF,
+            SpecialFunction::Turbo => SpecialPacket::FUNCTION_TURBO,
+        })
+    }
+}
+
+impl From<SpecialFunction> for SpecialPacket {
+    fn from(function: SpecialFunction) -> Self {
+        Self::from_function(function)
+    }
+}
+
+impl TryFrom<SpecialPacket> for SpecialFunction {
+    type Error = EncodeError;
+
+    fn try_from(packet: SpecialPacket) -> Result<Self, EncodeError> {
+        packet.to_function()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use strum::IntoEnumIterator;
+
+    const KNOWN_PACKETS: [(u64, SpecialFunction); 6] = [
+        (0xa208ffffff75, SpecialFunction::Led),
+        (0xa202ffffff7e, SpecialFunction::Swing),
+        (0xa201ffffff7c, SpecialFunction::Direct),
+        (0xa213ffffff6f, SpecialFunction::SilenceOff),
+        (0xa212ffffff6e, SpecialFunction::SilenceOn),
+        (0xa209ffffff74, SpecialFunction::Turbo),
+    ];
+
+    #[test]
+    fn test_decode() {
+        for (bits, function) in KNOWN_PACKETS {
+            assert_eq!(SpecialPacket(bits).to_function().unwrap(), function);
+        }
+    }
+
+    #[test]
+    fn test_encode() {
+        for (bits, function) in KNOWN_PACKETS {
+            assert_eq!(SpecialPacket::from_function(function).0, bits);
+        }
+
+        for function in SpecialFunction::iter() {
+            let packet = SpecialPacket::from(function);
+            assert_eq!(SpecialFunction::try_from(packet).unwrap(), function);
+        }
+    }
+
+    #[test]
+    fn test_checksum_mismatch() {
+        assert!(matches!(
+            SpecialPacket(0xa208ffffff74).to_function(),
+            Err(EncodeError::ChecksumMismatch)
+        ));
+    }
+}
@@ -0,0 +1,329 @@
+use std::time::Duration;
+
+use super::{
+    phy::{DecodeError, Phy, PhyError, PulseType, PREAMBLE},
+    recovery::Recovered,
+};
+
+/// Something that happened while decoding a continuous stream of pulses
+#[derive(Debug, Clone)]
+pub enum Event {
+    /// A message was received, and its repeat frame matched
+    Message(u64),
+    /// A message was reconstructed from a transmission whose repeat frame didn't match, with
+    /// recovery enabled
+    Recovered(Recovered),
+    /// The frame being decoded was dropped, decoding resumes at the next preamble
+    Error(PhyError),
+}
+
+#[derive(Debug, Clone, Copy)]
+enum State {
+    /// Waiting for a preamble, remembering the last pulse to detect it
+    Searching(Option<PulseType>),
+    /// Inside a frame, after the preamble
+    Frame {
+        bits: u64,
+        count: u32,
+        mark: Option<PulseType>,
+    },
+}
+
+/// Push-based decoder, accepting pulses one at a time. Unlike `Phy::decode`, it doesn't need the
+/// pulses of exactly one transmission: leading noise and partial frames are skipped, and any
+/// number of back-to-back transmissions can be decoded.
+pub struct StreamDecoder<'a> {
+    phy: &'a Phy,
+    state: State,
+    /// First frame of a transmission, waiting for its complemented repeat
+    pending: Option<u64>,
+    /// Whether transmissions whose frames don't match are reconstructed with `decode_recover`
+    recover: bool,
+    /// Pulses since the preamble of the first frame, kept while recovery is enabled
+    window: Vec<Duration>,
+}
+
+impl<'a> StreamDecoder<'a> {
+    const FRAME_BITS: u32 = 48;
+    const FRAME_MASK: u64 = 0xFFFF_FFFF_FFFF;
+    /// A frame, its trailing mark and the gap following it
+    const FRAME_WINDOW: usize = Phy::FRAME_PULSES + 2;
+    /// Noise in between the frames of a transmission beyond which the window stops growing
+    const MAX_WINDOW: usize = 4 * Self::FRAME_WINDOW;
+
+    pub fn new(phy: &'a Phy) -> Self {
+        Self {
+            phy,
+            state: State::Searching(None),
+            pending: None,
+            recover: false,
+            window: Vec::new(),
+        }
+    }
+
+    /// Enables error recovery, as with `Lennox::set_recovery`
+    pub fn with_recovery(mut self, enabled: bool) -> Self {
+        self.recover = enabled;
+        self
+    }
+
+    /// Feeds the next pulse, alternating between on and off durations
+    pub fn push(&mut self, duration: Duration) -> Option<Event> {
+        if self.recover {
+            self.window.push(duration);
+            if self.window.len() > Self::MAX_WINDOW {
+                self.window.drain(..self.window.len() - Self::MAX_WINDOW);
+            }
+        }
+
+        let pulse = match self.phy.codec().decode_pulse(duration) {
+            Ok(pulse) => pulse,
+            Err(e) => return self.resync(None, PhyError::PWMError(e)),
+        };
+
+        match self.state {
+            State::Searching(last) => {
+                // Outside of a transmission, only the pulses of the next preamble are needed
+                if self.pending.is_none() {
+                    let start = self.window.len().saturating_sub(2);
+                    self.window.drain(..start);
+                }
+
+                self.state = if (last, pulse) == (Some(PREAMBLE.0), PREAMBLE.1) {
+                    State::Frame {
+                        bits: 0,
+                        count: 0,
+                        mark: None,
+                    }
+                } else {
+                    State::Searching(Some(pulse))
+                };
+                None
+            }
+            State::Frame {
+                bits,
+                count,
+                mark: None,
+            } => {
+                self.state = State::Frame {
+                    bits,
+                    count,
+                    mark: Some(pulse),
+                };
+                None
+            }
+            State::Frame {
+                bits,
+                count,
+                mark: Some(mark),
+            } => self.push_pair(bits, count, (mark, pulse)),
+        }
+    }
+
+    /// Signals the end of the stream, reporting a transmission whose repeat never came
+    pub fn finish(&mut self) -> Option<Event> {
+        let frame_started = matches!(self.state, State::Frame { .. });
+        self.state = State::Searching(None);
+
+        if self.pending.take().is_some() || frame_started {
+            Some(Event::Error(DecodeError::TruncatedMessage.into()))
+        } else {
+            None
+        }
+    }
+
+    fn push_pair(&mut self, bits: u64, count: u32, pair: (PulseType, PulseType)) -> Option<Event> {
+        use PulseType::*;
+
+        let bit = match pair {
+            (Short, Short) => 0,
+            (Short, Long) => 1,
+            (Short, FiveThousand | Huge) if count == Self::FRAME_BITS => {
+                self.state = State::Searching(None);
+                return self.frame_complete(bits);
+            }
+            (Short, FiveThousand | Huge) => {
+                return self.resync(None, DecodeError::TruncatedMessage.into())
+            }
+            PREAMBLE => {
+                // A new frame started before the current one ended
+                self.pending = None;
+                self.state = State::Frame {
+                    bits: 0,
+                    count: 0,
+                    mark: None,
+                };
+                return Some(Event::Error(DecodeError::TruncatedMessage.into()));
+            }
+            any => return self.resync(Some(any.1), DecodeError::InvalidCombination(any).into()),
+        };
+
+        if count == Self::FRAME_BITS {
+            return self.resync(Some(pair.1), DecodeError::InvalidCombination(pair).into());
+        }
+
+        self.state = State::Frame {
+            bits: (bits << 1) | bit,
+            count: count + 1,
+            mark: None,
+        };
+        None
+    }
+
+    fn frame_complete(&mut self, bits: u64) -> Option<Event> {
+        match self.pending.take() {
+            None => {
+                self.pending = Some(bits);
+                None
+            }
+            Some(first) if first ^ bits == Self::FRAME_MASK => Some(Event::Message(first)),
+            Some(_) if self.recover => match self.phy.decode_recover(&self.window) {
+                Ok(recovered) => Some(Event::Recovered(recovered)),
+                Err(_) => {
+                    // Only this frame may start a new transmission
+                    let start = self.window.len().saturating_sub(Self::FRAME_WINDOW);
+                    self.window.drain(..start);
+                    self.pending = Some(bits);
+                    Some(Event::Error(DecodeError::RepeatMismatch.into()))
+                }
+            },
+            Some(_) => {
+                // The repeat of the previous frame was lost, this frame may start a new transmission
+                self.pending = Some(bits);
+                Some(Event::Error(DecodeError::RepeatMismatch.into()))
+            }
+        }
+    }
+
+    /// Drops the current frame and goes back to searching for a preamble, which may start with
+    /// the last pulse seen
+    fn resync(&mut self, last: Option<PulseType>, error: PhyError) -> Option<Event> {
+        let in_frame = matches!(self.state, State::Frame { .. });
+        self.state = State::Searching(last);
+
+        // Noise in between frames isn't an error, only report dropped frames
+        if in_frame {
+            self.pending = None;
+            Some(Event::Error(error))
+        } else {
+            None
+        }
+    }
+}
+
+impl Phy {
+    /// Creates a push-based decoder for continuous captures
+    pub fn stream(&self) -> StreamDecoder<'_> {
+        StreamDecoder::new(self)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn push_all(decoder: &mut StreamDecoder, pulses: &[Duration]) -> Vec<Event> {
+        pulses.iter().filter_map(|&p| decoder.push(p)).collect()
+    }
+
+    fn messages(events: &[Event]) -> Vec<u64> {
+        events
+            .iter()
+            .filter_map(|e| match e {
+                Event::Message(m) => Some(*m),
+                Event::Recovered(recovered) => Some(recovered.bits),
+                Event::Error(_) => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_back_to_back() {
+        let phy = Phy::new();
+        let mut pulses = vec![Duration::from_micros(1234), Duration::from_micros(80)];
+        pulses.extend(phy.encode(0xa12347ffffeb).unwrap());
+        pulses.extend(phy.encode(0xa1a347ffff6b).unwrap());
+
+        let mut decoder = phy.stream();
+        let events = push_all(&mut decoder, &pulses);
+        assert_eq!(messages(&events), vec![0xa12347ffffeb, 0xa1a347ffff6b]);
+        assert!(decoder.finish().is_none());
+    }
+
+    #[test]
+    fn test_partial_frame() {
+        let phy = Phy::new();
+        let partial = phy.encode(0xa12347ffffeb).unwrap();
+
+        // Cut on either a mark or a space
+        for cut in [49, 50] {
+            let mut pulses = partial[..cut].to_vec();
+            pulses.extend(phy.encode(0xa1a347ffff6b).unwrap());
+
+            let mut decoder = phy.stream();
+            let events = push_all(&mut decoder, &pulses);
+            assert_eq!(messages(&events), vec![0xa1a347ffff6b]);
+            assert!(events.iter().any(|e| matches!(e, Event::Error(_))));
+        }
+    }
+
+    #[test]
+    fn test_split_pushes() {
+        // A transmission split across several captures is still decoded
+        let phy = Phy::new();
+        let pulses = phy.encode(0xa1a347ffff6b).unwrap();
+        let (a, b) = pulses.split_at(57);
+
+        let mut decoder = phy.stream();
+        assert!(push_all(&mut decoder, a).is_empty());
+        assert_eq!(messages(&push_all(&mut decoder, b)), vec![0xa1a347ffff6b]);
+    }
+
+    #[test]
+    fn test_truncated() {
+        let phy = Phy::new();
+        let pulses = phy.encode(0xa1a347ffff6b).unwrap();
+
+        let mut decoder = phy.stream();
+        assert!(push_all(&mut decoder, &pulses[..pulses.len() / 2]).is_empty());
+        assert!(matches!(decoder.finish(), Some(Event::Error(_))));
+    }
+
+    #[test]
+    fn test_recovery() {
+        use crate::lennox::recovery::{Correction, Frame};
+
+        // Bit 10 is a 1, its space is shortened until it reads as a 0 in the first frame
+        let phy = Phy::new();
+        let mut pulses = vec![Duration::from_micros(1234), Duration::from_micros(80)];
+        let damaged = pulses.len() + 2 + 2 * (47 - 10) + 1;
+        pulses.extend(phy.encode(0xa1a347ffff6b).unwrap());
+        pulses[damaged] = Duration::from_micros(500);
+        pulses.extend(phy.encode(0xa12347ffffeb).unwrap());
+
+        let events = push_all(&mut phy.stream(), &pulses);
+        assert_eq!(messages(&events), vec![0xa12347ffffeb]);
+        assert!(matches!(
+            events[0],
+            Event::Error(PhyError::DecodeError(DecodeError::RepeatMismatch))
+        ));
+
+        let mut decoder = phy.stream().with_recovery(true);
+        let events = push_all(&mut decoder, &pulses);
+        match &events[..] {
+            [Event::Recovered(recovered), Event::Message(next)] => {
+                assert_eq!(recovered.bits, 0xa1a347ffff6b);
+                assert_eq!(
+                    recovered.corrected,
+                    [Correction {
+                        bit: 10,
+                        frame: Frame::First
+                    }]
+                );
+                assert_eq!(*next, 0xa12347ffffeb);
+            }
+            events => panic!("unexpected events: {:?}", events),
+        }
+        assert!(decoder.finish().is_none());
+    }
+}
@@ -0,0 +1,234 @@
+/** LIRC support: lircd.conf generation for LIRC transmitters, and parsing of mode2 captures
+ * lircd.conf format: https://www.lirc.org/html/lircd.conf.html
+ * mode2 prints one `pulse N` or `space N` line per duration in microseconds, and `timeout N` when
+ * the receiver's timeout expires.
+ */
+use std::{
+    fmt::Write,
+    io::{self, BufRead},
+    time::Duration,
+};
+
+use thiserror::Error;
+
+use crate::protocol::{self, Protocol};
+
+/// Generates a lircd.conf remote holding a raw code for every state
+pub fn gen_lircd_conf(protocol: &dyn Protocol) -> anyhow::Result<()> {
+    print!("{}", lircd_conf(protocol)?);
+    Ok(())
+}
+
+fn lircd_conf(protocol: &dyn Protocol) -> anyhow::Result<String> {
+    let mut conf = String::new();
+
+    writeln!(
+        conf,
+        "# {} {}, generated by heatpump",
+        protocol.manufacturer(),
+        protocol.models().join(", ")
+    )?;
+    writeln!(conf, "begin remote")?;
+    writeln!(conf)?;
+    writeln!(conf, "  name        {}", protocol.name())?;
+    writeln!(conf, "  flags       RAW_CODES")?;
+    writeln!(conf, "  eps         30")?;
+    writeln!(conf, "  aeps        100")?;
+    writeln!(conf, "  frequency   {}", protocol.carrier_frequency())?;
+    writeln!(conf, "  gap         {}", GAP.as_micros())?;
+    writeln!(conf)?;
+    writeln!(conf, "  begin raw_codes")?;
+
+    for (name, state) in protocol::named_states(protocol) {
+        let mut pulses = protocol.encode(&state)?;
+        // Raw codes end on a pulse, the silence that follows is the remote's gap
+        if pulses.len().is_multiple_of(2) {
+            pulses.pop();
+        }
+
+        writeln!(conf)?;
+        writeln!(conf, "    name {}", name)?;
+        for line in pulses.chunks(8) {
+            let line: Vec<_> = line.iter().map(|p| p.as_micros().to_string()).collect();
+            writeln!(conf, "      {}", line.join(" "))?;
+        }
+    }
+
+    writeln!(conf)?;
+    writeln!(conf, "  end raw_codes")?;
+    writeln!(conf)?;
+    writeln!(conf, "end remote")?;
+
+    Ok(conf)
+}
+
+/// Silence between transmissions, written as the remote's gap and ending every mode2 capture
+const GAP: Duration = Duration::from_millis(100);
+
+/// Spaces at least this long end a mode2 capture
+const SPLIT_GAP: Duration = Duration::from_millis(20);
+
+#[derive(Error, Debug)]
+pub enum Mode2Error {
+    #[error("invalid mode2 line: {0}")]
+    InvalidLine(String),
+    #[error("failed to read mode2 output: {0}")]
+    Io(#[from] io::Error),
+}
+
+/// Reads mode2 output, splitting it into transmissions at timeouts and long spaces. Each
+/// transmission starts with a pulse and ends with a 100ms space, like Broadlink captures.
+pub struct Mode2Reader<R> {
+    lines: io::Lines<R>,
+    pulses: Vec<Duration>,
+}
+
+impl<R: BufRead> Mode2Reader<R> {
+    pub fn new(input: R) -> Self {
+        Self {
+            lines: input.lines(),
+            pulses: Vec::new(),
+        }
+    }
+
+    /// Parses a line, returning the transmission it completes, if any
+    fn push_line(&mut self, line: &str) -> Result<Option<Vec<Duration>>, Mode2Error> {
+        const KINDS: [&str; 3] = ["pulse", "space", "timeout"];
+
+        let words: Vec<_> = line.split_whitespace().collect();
+        let (kind, value) = match words[..] {
+            [kind, value] if KINDS.contains(&kind) => (kind, value),
+            [kind, ..] if KINDS.contains(&kind) => {
+                return Err(Mode2Error::InvalidLine(line.into()))
+            }
+            // Other lines are informational, such as the driver in use
+            _ => return Ok(None),
+        };
+
+        let duration = value
+            .parse()
+            .map(Duration::from_micros)
+            .map_err(|_| Mode2Error::InvalidLine(line.into()))?;
+
+        match kind {
+            "pulse" => {
+                // Consecutive pulses are reported when the receiver's buffer wraps
+                if self.pulses.len() % 2 == 1 {
+                    *self.pulses.last_mut().unwrap() += duration;
+                } else {
+                    self.pulses.push(duration);
+                }
+                Ok(None)
+            }
+            "space" if duration < SPLIT_GAP => {
+                match self.pulses.len() % 2 {
+                    // Leading spaces are the silence before the first transmission
+                    0 if self.pulses.is_empty() => {}
+                    0 => *self.pulses.last_mut().unwrap() += duration,
+                    _ => self.pulses.push(duration),
+                }
+                Ok(None)
+            }
+            _ => Ok(self.take()),
+        }
+    }
+
+    fn take(&mut self) -> Option<Vec<Duration>> {
+        if self.pulses.is_empty() {
+            return None;
+        }
+
+        let mut pulses = std::mem::take(&mut self.pulses);
+        if pulses.len().is_multiple_of(2) {
+            pulses.pop();
+        }
+        pulses.push(GAP);
+        Some(pulses)
+    }
+}
+
+impl<R: BufRead> Iterator for Mode2Reader<R> {
+    type Item = Result<Vec<Duration>, Mode2Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while let Some(line) = self.lines.next() {
+            match line
+                .map_err(Mode2Error::from)
+                .and_then(|line| self.push_line(&line))
+            {
+                Ok(Some(pulses)) => return Some(Ok(pulses)),
+                Ok(None) => {}
+                Err(e) => return Some(Err(e)),
+            }
+        }
+
+        self.take().map(Ok)
+    }
+}
+
+/// Formats pulses as mode2 output
+pub fn to_mode2(pulses: &[Duration]) -> String {
+    pulses
+        .iter()
+        .enumerate()
+        .map(|(i, p)| {
+            let kind = if i % 2 == 0 { "pulse" } else { "space" };
+            format!("{} {}", kind, p.as_micros())
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::lennox::Lennox;
+
+    #[test]
+    fn test_lircd_conf() {
+        let lennox = Lennox::new();
+        let conf = lircd_conf(&lennox).unwrap();
+        assert!(conf.contains("flags       RAW_CODES"));
+        assert!(conf.contains("frequency   38000"));
+        assert!(conf.contains("    name off\n"));
+        assert!(conf.contains("    name heat_auto_24\n"));
+        assert!(conf.contains("    name sleep_cool_max_17\n"));
+        assert!(conf.contains("    name fan_min\n"));
+        assert!(!conf.contains("name sleep_fan"));
+    }
+
+    #[test]
+    fn test_mode2() {
+        let lennox = Lennox::new();
+        let state = protocol::named_states(&lennox)[1].1;
+        let pulses = lennox.encode(&state).unwrap();
+
+        // mode2 starts with the silence preceding the first pulse, and ends with a timeout
+        let capture = format!(
+            "space 16777215\n{}\ntimeout 120000\n{}\n",
+            to_mode2(&pulses[..pulses.len() - 1]),
+            to_mode2(&pulses[..pulses.len() - 1]),
+        );
+
+        let transmissions = Mode2Reader::new(capture.as_bytes())
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        assert_eq!(transmissions.len(), 2);
+        for transmission in transmissions {
+            assert_eq!(transmission.len(), pulses.len());
+            let decoded = lennox.decode(&transmission).unwrap();
+            assert_eq!(decoded.mode, state.mode);
+            assert_eq!(decoded.temperature, state.temperature);
+        }
+    }
+
+    #[test]
+    fn test_mode2_invalid() {
+        let mut reader =
+            Mode2Reader::new("Using driver default\npulse 560\nspace abc\n".as_bytes());
+        assert!(matches!(
+            reader.next(),
+            Some(Err(Mode2Error::InvalidLine(_)))
+        ));
+    }
+}
@@ -1,56 +1,390 @@
-use std::io::{self, Write};
+use std::{
+    io::{self, BufRead, Write},
+    net::{IpAddr, Ipv4Addr, SocketAddr},
+    path::PathBuf,
+    time::Duration,
+};
 
 use bytes::Bytes;
 use clap::Parser;
-use irp::InfraredData;
+use anyhow::{anyhow, bail};
 
 use crate::{
-    broadlink::Recording,
-    lennox::{packet::Packet, ControlState, Phy},
+    broadlink::{
+        client::{self, Client, DeviceOpts},
+        mock::MockDevice,
+        Recording,
+    },
+    detect::Nec,
+    flipper::{IrFile, Signal},
+    lennox::{follow_me::FollowMe, special::SpecialFunction, stream::Event, Command, Lennox},
+    ook::{Frame, Ook, RfCommand},
+    pronto::Pronto,
+    protocol::Protocol,
+    state::{ControlState, StateUpdate},
+    store::Store,
+    transmitter::{Reconnecting, Transmitter},
 };
 
 mod broadlink;
+mod detect;
+mod esp;
+mod flipper;
+mod ha_storage;
 mod lennox;
+mod lirc;
+mod mqtt;
+mod ook;
+mod pronto;
+mod protocol;
 mod pwm;
+mod schedule;
+mod server;
 mod smartir;
+mod state;
+mod store;
+mod transmitter;
+mod tuya;
 
 #[derive(Clone, Parser, Debug)]
 #[clap(version=env!("CARGO_PKG_VERSION"), author=env!("CARGO_PKG_AUTHORS"))]
 struct Opts {
+    /// Heat pump protocol used by the decode, set-state and smart-ir commands
+    #[clap(short = 'P', long, global = true, default_value = "lennox")]
+    protocol: String,
+
+    /// Pulse timings produced by the calibrate command, replacing the protocol's nominal timings
+    #[clap(long, global = true)]
+    timings: Option<PathBuf>,
+
+    /// Reconstruct damaged transmissions from the repeat frame and checksum when decoding
+    #[clap(long, global = true)]
+    recover: bool,
+
+    /// Format of the IR codes read from stdin and printed to stdout: broadlink, pronto, tuya,
+    /// mode2 or flipper. esphome and irremote can only be printed.
+    #[clap(short = 'F', long, global = true, default_value = "broadlink")]
+    format: Format,
+
+    /// File holding the assumed state of every unit, shared by the update, mqtt and serve
+    /// commands. Defaults to $XDG_STATE_HOME/heatpump/states.json.
+    #[clap(long, global = true)]
+    store: Option<PathBuf>,
+
     #[clap(subcommand)]
     subcmd: SubCommand,
 }
 
+impl Opts {
+    /// The protocol selected with --protocol, using the --timings and --recover if given
+    fn protocol(&self) -> anyhow::Result<Box<dyn Protocol>> {
+        let mut protocol = protocol::by_name(&self.protocol)?;
+        if let Some(timings) = self.load_timings()? {
+            protocol.set_timings(timings)?;
+        }
+        if self.recover {
+            protocol.set_recovery(true)?;
+        }
+        Ok(protocol)
+    }
+
+    /// Lennox-specific commands use the Lennox protocol regardless of --protocol, with the
+    /// --timings and --recover if given
+    fn lennox(&self) -> anyhow::Result<Lennox> {
+        let mut lennox = Lennox::new();
+        if let Some(timings) = self.load_timings()? {
+            lennox.set_timings(timings)?;
+        }
+        if self.recover {
+            lennox.set_recovery(true)?;
+        }
+        Ok(lennox)
+    }
+
+    fn store(&self) -> Store {
+        Store::new(self.store.clone().unwrap_or_else(Store::default_path))
+    }
+
+    fn load_timings(&self) -> anyhow::Result<Option<serde_json::Value>> {
+        match &self.timings {
+            Some(path) => Ok(Some(serde_json::from_reader(std::fs::File::open(path)?)?)),
+            None => Ok(None),
+        }
+    }
+}
+
+/// Encoding of IR codes
+#[derive(Debug, Clone, Copy, PartialEq, Eq, strum::EnumString, strum::AsRefStr)]
+#[strum(serialize_all = "lowercase", ascii_case_insensitive)]
+enum Format {
+    /// Broadlink packets, one per line, printed in hex and read in either hex or base64
+    Broadlink,
+    /// Pronto Hex learned codes, one per line
+    Pronto,
+    /// Tuya IR blaster codes (ir_code), one per line
+    Tuya,
+    /// LIRC mode2 output, one pulse or space per line
+    Mode2,
+    /// Flipper Zero .ir file, holding any number of raw signals
+    Flipper,
+    /// ESPHome remote_transmitter.transmit_raw action
+    Esphome,
+    /// C array for IRremoteESP8266's sendRaw
+    Irremote,
+}
+
+impl Format {
+    /// Reads every code from the input
+    fn read<'a>(
+        self,
+        input: impl BufRead + 'a,
+    ) -> Box<dyn Iterator<Item = anyhow::Result<Recording>> + 'a> {
+        match self {
+            Format::Broadlink => Box::new(input.lines().map(|line| parse_recording(&line?))),
+            Format::Pronto => Box::new(input.lines().map(|line| {
+                Ok(Recording::new_ir(line?.parse::<Pronto>()?.pulses()))
+            })),
+            Format::Tuya => Box::new(
+                input
+                    .lines()
+                    .map(|line| Ok(Recording::new_ir(tuya::decode(&line?)?))),
+            ),
+            Format::Mode2 => Box::new(
+                lirc::Mode2Reader::new(input).map(|pulses| Ok(Recording::new_ir(pulses?))),
+            ),
+            Format::Flipper => {
+                let file = io::read_to_string(input)
+                    .map_err(anyhow::Error::from)
+                    .and_then(|file| Ok(file.parse::<IrFile>()?));
+
+                match file {
+                    Ok(file) => Box::new(file.signals.into_iter().map(|signal| {
+                        Ok(Recording::new_ir(signal.pulses()?.to_vec()))
+                    })),
+                    Err(e) => Box::new(std::iter::once(Err(e))),
+                }
+            }
+            Format::Esphome | Format::Irremote => Box::new(std::iter::once(Err(anyhow!(
+                "{} codes can't be read",
+                self.as_ref()
+            )))),
+        }
+    }
+
+    /// Formats pulses modulated at the given carrier frequency (in Hz)
+    fn format(self, frequency: u32, pulses: Vec<Duration>) -> String {
+        let code = match self {
+            Format::Broadlink => hex::encode(Recording::new_ir(pulses).to_bytes()),
+            Format::Pronto => Pronto::new(frequency, pulses).to_string(),
+            Format::Tuya => tuya::encode(&pulses),
+            Format::Mode2 => lirc::to_mode2(&pulses),
+            Format::Flipper => IrFile {
+                signals: vec![Signal::new_raw("command", frequency, pulses)],
+            }
+            .to_string(),
+            Format::Esphome => esp::esphome_action(frequency, &pulses),
+            Format::Irremote => esp::irremote_array("rawData", frequency, &pulses),
+        };
+
+        // Multi-line formats end with a newline, which is added when printing
+        code.trim_end().to_string()
+    }
+}
+
 #[derive(Clone, Parser, Debug)]
 enum SubCommand {
-    /// Decode hex-encoded commands in the broadlink format from stdin, and print them to stdout
+    /// Decode commands read from stdin, and print them to stdout
     Decode,
 
-    /// Encodes a state message from the given arguments, outputs it to stdout in broadlink hex format
+    /// Encodes a state message from the given arguments, outputs it to stdout
     SetState(ControlState),
 
-    /// Decodes a broadlink message into a series of pulse length (in microseconds)
+    /// Merges the given settings into the stored state of a unit, and sends the resulting state
+    Update {
+        /// Unit the state is stored under
+        #[clap(long, default_value = "heatpump")]
+        unit: String,
+
+        #[clap(flatten)]
+        update: StateUpdate,
+
+        #[clap(flatten)]
+        backend: BackendOpts,
+    },
+
+    /// Print the stored state of every unit as JSON
+    Units,
+
+    /// Encodes a special function command (LED, swing, silence, ...), outputs it to stdout
+    Special {
+        /// Function to trigger
+        function: SpecialFunction,
+    },
+
+    /// Encodes a follow-me report with the given room temperature, outputs it to stdout
+    FollowMe(FollowMe),
+
+    /// Continuously decode Lennox commands read from stdin, even when they hold noise, partial
+    /// frames or several transmissions
+    Listen,
+
+    /// Decodes a message into a series of pulse length (in microseconds)
     Broadlink,
 
     /// IRP decode
     Irp,
 
+    /// Try every known decoder on messages read from stdin, and report which ones match
+    Detect,
+
+    /// Fit the protocol's pulse timings to messages read from stdin, and print them as JSON
+    Calibrate,
+
     /// Generate a SmartIR code file from all possible states
     SmartIR,
+
+    /// Generate a LIRC lircd.conf remote with a raw code for every possible state
+    Lirc,
+
+    /// Generate a Flipper Zero .ir file with a signal for every possible state
+    Flipper,
+
+    /// Generate an ESPHome configuration block with a button sending every possible state
+    Esphome,
+
+    /// Generate a C header with an IRremoteESP8266 raw array for every possible state
+    Irremote,
+
+    /// Export every possible state to, or audit the codes of, a Home Assistant Broadlink
+    /// storage file (.storage/broadlink_remote_<mac>_codes)
+    #[clap(subcommand)]
+    HaStorage(ha_storage::HaStorageCommand),
+
+    /// Decode or encode fixed-code (PT2262/EV1527-style) 315/433MHz remote codes
+    #[clap(subcommand)]
+    Rf(RfCommand),
+
+    /// List the Broadlink devices answering on the local network
+    Discover {
+        /// Seconds to wait for devices to answer
+        #[clap(long, default_value = "3")]
+        timeout: u64,
+    },
+
+    /// Send codes read from stdin through a Broadlink device
+    Send(DeviceOpts),
+
+    /// Put a Broadlink device in learning mode, and print the codes it learns
+    Learn {
+        #[clap(flatten)]
+        device: DeviceOpts,
+
+        /// Stop after learning this many codes
+        #[clap(long)]
+        count: Option<usize>,
+    },
+
+    /// Run a mock Broadlink device on the local machine, decoding the codes sent to it
+    MockDevice {
+        /// Address to listen on
+        #[clap(long, default_value = "127.0.0.1:8080")]
+        listen: SocketAddr,
+
+        /// Device type: RM4 devices (e.g. 0x5216) frame commands differently from older ones
+        /// (e.g. 0x2712)
+        #[clap(long, default_value = "0x5216", value_parser = parse_devtype)]
+        devtype: u16,
+
+        /// File of codes returned by learning mode, one per learned code
+        #[clap(long)]
+        teach: Option<PathBuf>,
+    },
+
+    /// Expose the heat pump as a Home Assistant climate entity over MQTT, sending the states
+    /// set from Home Assistant through a Broadlink device
+    Mqtt {
+        #[clap(flatten)]
+        mqtt: mqtt::MqttOpts,
+
+        #[clap(flatten)]
+        backend: BackendOpts,
+    },
+
+    /// Send the settings of a weekly schedule to units as they're due
+    Schedule {
+        #[clap(flatten)]
+        schedule: schedule::ScheduleOpts,
+
+        #[clap(flatten)]
+        backend: BackendOpts,
+    },
+
+    /// Serve an HTTP/JSON API encoding, decoding and sending states: POST /encode, POST /decode,
+    /// GET /states and POST /send
+    Serve {
+        /// Address to listen on
+        #[clap(long, default_value = "127.0.0.1:8000")]
+        listen: SocketAddr,
+
+        #[clap(flatten)]
+        backend: BackendOpts,
+    },
 }
 
-/// Read hex-encoded messages from stdin, convert them and print their decoded u64 hex value
-fn decode() -> anyhow::Result<()> {
-    let phy = Phy::new();
+/// Where long-running commands send codes
+#[derive(Clone, Parser, Debug)]
+struct BackendOpts {
+    #[clap(flatten)]
+    device: DeviceOpts,
+
+    /// Print the codes to stdout instead of sending them through a Broadlink device
+    #[clap(long)]
+    print: bool,
+}
+
+impl BackendOpts {
+    fn transmitter(&self, format: Format) -> anyhow::Result<Box<dyn Transmitter>> {
+        if self.print {
+            return Ok(Box::new(move |frequency, pulses| {
+                println!("{}", format.format(frequency, pulses));
+                Ok(io::stdout().flush()?)
+            }));
+        }
+
+        let transmitter = Reconnecting::connect(self.device.clone())?;
+        if let Some(client) = transmitter.client() {
+            eprintln!("Sending through {}", client.device());
+        }
+        Ok(Box::new(transmitter))
+    }
+}
 
-    let stdin = std::io::stdin();
-    for line in stdin.lines() {
-        let recording = Recording::from_bytes(Bytes::from(base64::decode(line?)?))?;
-        let msg = phy.decode(recording.pulses.iter().copied())?;
-        println!("Recv: {:x} {:b}", msg, msg);
+fn parse_devtype(devtype: &str) -> Result<u16, std::num::ParseIntError> {
+    match devtype.strip_prefix("0x") {
+        Some(hex) => u16::from_str_radix(hex, 16),
+        None => devtype.parse(),
+    }
+}
 
-        let state = Packet(msg).to_control_state();
-        println!("Decode: {:?}", state);
+/// Read messages from stdin, and print their decoded value
+fn decode(protocol: &dyn Protocol, format: Format) -> anyhow::Result<()> {
+    for recording in format.read(io::stdin().lock()) {
+        let recording = match recording {
+            Ok(recording) => recording,
+            Err(e) => {
+                println!("Error: {:#}", e);
+                continue;
+            }
+        };
+        // RF remotes don't use heat pump protocols, only fixed codes
+        let description = match recording.transport.is_rf() {
+            true => ook::describe(&recording),
+            false => protocol.describe(&recording.pulses()),
+        };
+        match description {
+            Ok(description) => println!("{}", description),
+            Err(e) => println!("Error: {:#}", e),
+        }
 
         io::stdout().flush()?;
     }
@@ -58,27 +392,62 @@ fn decode() -> anyhow::Result<()> {
     Ok(())
 }
 
-/// Encode ControlState into a broadlink-formatted message, and print it to stdout
-fn set_state(state: ControlState) -> anyhow::Result<()> {
-    let packet: Packet = Packet::from_control_state(&state)?;
-    let pulses = Phy::new().encode(packet.0)?;
-    let recording_bytes = Recording::new_ir(pulses).to_bytes();
+/// Encode ControlState into a message, and print it to stdout
+fn set_state(protocol: &dyn Protocol, format: Format, state: ControlState) -> anyhow::Result<()> {
+    println!("{}", encode_state(protocol, format, &state)?);
+    Ok(())
+}
 
-    println!("{}", hex::encode(recording_bytes));
+fn encode_state(
+    protocol: &dyn Protocol,
+    format: Format,
+    state: &ControlState,
+) -> anyhow::Result<String> {
+    let pulses = protocol.encode(state)?;
+    Ok(format.format(protocol.carrier_frequency(), pulses))
+}
+
+/// Merge settings into the stored state of a unit, and send it. The stored state is only changed
+/// once sent.
+fn update(
+    protocol: &dyn Protocol,
+    format: Format,
+    store: &Store,
+    unit: &str,
+    update: StateUpdate,
+    backend: &BackendOpts,
+) -> anyhow::Result<()> {
+    let mut transmitter = backend.transmitter(format)?;
+    let state = store.update(unit, protocol::initial_state(protocol), |state| {
+        let state = update.apply(state);
+        transmitter.transmit(protocol.carrier_frequency(), protocol.encode(&state)?)?;
+        anyhow::Ok(state)
+    })?;
 
+    eprintln!("{}: {:?}", unit, state);
     Ok(())
 }
 
-fn broadlink_decode() -> anyhow::Result<()> {
-    let stdin = std::io::stdin();
-    for line in stdin.lines() {
-        let line = base64::decode(line?)?;
-        //hex::decode(line?)?;
-        let recording = Recording::from_bytes(Bytes::copy_from_slice(&line))?;
-        // println!("{:?}", recording.pulses.iter().map(|p| p.as_micros()).collect::<Vec<_>>());
+/// Encode a Lennox-specific command into a message, and print it to stdout
+fn send_command(lennox: &Lennox, format: Format, command: Command) -> anyhow::Result<()> {
+    let pulses = lennox.encode_command(&command)?;
+    println!("{}", format.format(lennox.carrier_frequency(), pulses));
+    Ok(())
+}
+
+fn broadlink_decode(format: Format) -> anyhow::Result<()> {
+    for recording in format.read(io::stdin().lock()) {
+        let recording = match recording {
+            Ok(recording) => recording,
+            Err(e) => {
+                println!("Error: {:#}", e);
+                continue;
+            }
+        };
+        // println!("{:?}", recording.pulses().iter().map(|p| p.as_micros()).collect::<Vec<_>>());
 
         let mut sign = false;
-        recording.pulses.into_iter().for_each(|p| {
+        recording.pulses().into_iter().for_each(|p| {
             sign = !sign;
             if sign {
                 print!("+");
@@ -92,59 +461,376 @@ fn broadlink_decode() -> anyhow::Result<()> {
     Ok(())
 }
 
-fn irp_decode() -> anyhow::Result<()> {
-    use irp::Irp;
+fn irp_decode(format: Format) -> anyhow::Result<()> {
+    let nec = Nec::new();
+
+    for recording in format.read(io::stdin().lock()) {
+        let res = recording.and_then(|recording| {
+            nec.decode(&recording)
+                .ok_or_else(|| anyhow!("no NEC frame found"))
+        });
+
+        match res {
+            Ok(res) => {
+                for (field, value) in res {
+                    print!("{}: {} ", field, value);
+                }
+                println!();
+            }
+            Err(e) => println!("Error: {:#}", e),
+        }
+    }
+
+    Ok(())
+}
+
+/// Read messages from stdin, and report which decoders recognize them. The selected protocol is
+/// tried with the --timings and --recover if given.
+fn detect(protocol: Box<dyn Protocol>, format: Format) -> anyhow::Result<()> {
+    let detectors = detect::detectors(protocol);
+    for recording in format.read(io::stdin().lock()) {
+        let recording = match recording {
+            Ok(recording) => recording,
+            Err(e) => {
+                println!("Error: {:#}\n", e);
+                continue;
+            }
+        };
+
+        for detection in detect::detect(&detectors, &recording) {
+            match detection.result {
+                Ok(m) => println!(
+                    "{}: match (confidence {:.2})\n{}",
+                    detection.decoder, m.confidence, m.payload
+                ),
+                Err(e) => println!("{}: no match ({:#})", detection.decoder, e),
+            }
+        }
+        println!();
+
+        io::stdout().flush()?;
+    }
+
+    Ok(())
+}
+
+/// Read messages from stdin, and fit the protocol's pulse timings to them
+fn calibrate(protocol: &dyn Protocol, format: Format) -> anyhow::Result<()> {
+    let mut pulses = Vec::new();
+
+    for recording in format.read(io::stdin().lock()) {
+        pulses.extend(recording?.pulses());
+    }
+
+    let timings = protocol.calibrate(&pulses)?;
+    println!("{}", serde_json::to_string_pretty(&timings)?);
+
+    Ok(())
+}
+
+/// Continuously read messages from stdin, and print every Lennox command they contain. Messages
+/// don't need to be aligned on transmissions, noise and partial frames are skipped.
+fn listen(lennox: &Lennox, format: Format) -> anyhow::Result<()> {
+    let mut decoder = lennox.stream();
 
-    const IRP_48_NEC1: &'static str = "{38.4k,564}<1,-1|1,-3>(16,-8,D:8,S:8,F:8,~F:8,E:8,~E:8,1,^108m,(16,-4,1,^108m)*)[D:0..255,S:0..255=255-D,F:0..255,E:0..255]";
-    let nfa = Irp::parse(IRP_48_NEC1)
-        .expect("irp parse")
-        .compile()
-        .expect("irp compile");
+    let print_event = |event| {
+        match event {
+            Event::Message(msg) => {
+                println!("Recv: {:x} {:b}", msg, msg);
+                println!("Decode: {:?}", Command::decode(msg));
+            }
+            Event::Recovered(recovered) => {
+                let msg = recovered.bits;
+                println!("Recv: {:x} {:b}", msg, msg);
+                println!("Decode: {:?}", Command::decode(msg));
+                println!(
+                    "Corrected: {}",
+                    lennox::describe_corrections(&recovered.corrected)
+                );
+            }
+            Event::Error(e) => println!("Error: {}", e),
+        }
+        io::stdout().flush()
+    };
 
-    let stdin = std::io::stdin();
-    for line in stdin.lines() {
-        let line = line?;
-        let pulses = {
-            let recording = Recording::from_bytes(Bytes::copy_from_slice(&hex::decode(line)?))?;
-            InfraredData::from_u32_slice(&recording.to_pulses())
+    for recording in format.read(io::stdin().lock()) {
+        let recording = match recording {
+            Ok(recording) => recording,
+            Err(e) => {
+                println!("Error: {:#}", e);
+                continue;
+            }
         };
 
-        let res = {
-            let mut decoder = nfa.decoder(100, 30, 20000);
-            for pulse in pulses {
-                decoder.input(pulse);
+        for pulse in recording.pulses() {
+            if let Some(event) = decoder.push(pulse) {
+                print_event(event)?;
             }
-            let mut decoded: Vec<_> = decoder.get().unwrap().into_iter().collect();
-            decoded.sort_by_key(|f| f.0.clone());
-            decoded
+        }
+    }
+
+    if let Some(event) = decoder.finish() {
+        print_event(event)?;
+    }
+
+    Ok(())
+}
+
+/// Decode RF captures read from stdin, or print an RF broadlink code
+fn rf(format: Format, command: RfCommand) -> anyhow::Result<()> {
+    match command {
+        RfCommand::Decode => {
+            for recording in format.read(io::stdin().lock()) {
+                match recording.and_then(|recording| ook::describe(&recording)) {
+                    Ok(description) => println!("{}", description),
+                    Err(e) => println!("Error: {:#}", e),
+                }
+            }
+        }
+        RfCommand::Encode {
+            code,
+            bits,
+            tristate,
+            unit,
+            band,
+            frames,
+            repeat,
+        } => {
+            if format != Format::Broadlink {
+                bail!("RF codes can only be printed as broadlink codes");
+            }
+
+            let frame = match (code, tristate) {
+                (_, Some(tristate)) => Frame::from_tristate(&tristate)?,
+                (Some(code), None) => Frame::from_hex(&code, bits)?,
+                (None, None) => bail!("either --code or --tristate is required"),
+            };
+            let pulses = Ook::new(Duration::from_micros(unit)).encode(&frame, frames);
+
+            let recording = match band {
+                433 => Recording::new_rf433(pulses, repeat),
+                315 => Recording::new_rf315(pulses, repeat),
+                _ => bail!("unknown band: {}MHz, expected 433 or 315", band),
+            };
+            println!("{}", hex::encode(recording.to_bytes()));
+        }
+    }
+
+    Ok(())
+}
+
+/// Print every Broadlink device answering a discovery broadcast
+fn discover(timeout: u64) -> anyhow::Result<()> {
+    let broadcast = SocketAddr::new(IpAddr::V4(Ipv4Addr::BROADCAST), client::PORT);
+    for device in client::discover(broadcast, Duration::from_secs(timeout))? {
+        println!("{}", device);
+    }
+
+    Ok(())
+}
+
+/// Send every code read from the input
+fn send(
+    format: Format,
+    device: &DeviceOpts,
+    input: impl BufRead,
+    mut output: impl Write,
+) -> anyhow::Result<()> {
+    let mut client = Client::connect(device.device()?, device.timeout())?;
+
+    for recording in format.read(input) {
+        client.send(&recording?)?;
+        writeln!(output, "Sent")?;
+        output.flush()?;
+    }
+
+    Ok(())
+}
+
+/// Print codes as they're learned by the device
+fn learn(format: Format, device: &DeviceOpts, count: Option<usize>) -> anyhow::Result<()> {
+    // Devices leave learning mode after a while, it's entered again until a code is learned
+    const LEARNING_TIMEOUT: Duration = Duration::from_secs(20);
+
+    let mut client = Client::connect(device.device()?, device.timeout())?;
+    eprintln!("Learning from {}", client.device());
+
+    let mut learned = 0;
+    while count.is_none_or(|count| learned < count) {
+        let recording = match client.learn(LEARNING_TIMEOUT)? {
+            Some(recording) => recording,
+            None => continue,
+        };
+
+        // Broadlink codes are printed as learned, keeping RF codes and the exact timings
+        match format {
+            Format::Broadlink => println!("{}", hex::encode(recording.to_bytes())),
+            _ => println!("{}", format.format(38_000, recording.pulses())),
+        }
+        io::stdout().flush()?;
+        learned += 1;
+    }
+
+    Ok(())
+}
+
+/// Run a mock device until interrupted, printing every code it receives
+fn mock_device(
+    protocol: &dyn Protocol,
+    format: Format,
+    listen: SocketAddr,
+    devtype: u16,
+    teach: Option<PathBuf>,
+) -> anyhow::Result<()> {
+    let mock = MockDevice::bind(listen, devtype)?;
+    if let Some(path) = teach {
+        for recording in format.read(io::BufReader::new(std::fs::File::open(path)?)) {
+            mock.teach(recording?);
+        }
+    }
+    eprintln!("Mock device 0x{:04x} listening on {}", devtype, mock.addr());
+
+    for recording in mock.subscribe() {
+        println!("Received: {}", hex::encode(recording.to_bytes()));
+        let description = match recording.transport.is_rf() {
+            true => ook::describe(&recording),
+            false => protocol.describe(&recording.pulses()),
         };
-        for (field, value) in res {
-            print!("{}: {} ", field, value);
+        match description {
+            Ok(description) => println!("{}", description),
+            Err(e) => println!("Error: {:#}", e),
         }
-        println!("");
+        io::stdout().flush()?;
     }
 
     Ok(())
 }
 
+/// Parses a broadlink message in either hex or base64
+fn parse_recording(line: &str) -> anyhow::Result<Recording> {
+    let line = line.trim();
+    let bytes = hex::decode(line).or_else(|_| base64::decode(line))?;
+    Ok(Recording::from_bytes(Bytes::from(bytes))?)
+}
+
 fn main() -> anyhow::Result<()> {
     let opts: Opts = Opts::parse();
-    match opts.subcmd {
-        SubCommand::Decode => decode(),
-        SubCommand::SetState(state) => set_state(state),
-        SubCommand::Broadlink => broadlink_decode(),
-        SubCommand::Irp => irp_decode(),
-        SubCommand::SmartIR => smartir::gen_smartir(),
+
+    let format = opts.format;
+
+    match opts.subcmd.clone() {
+        SubCommand::Decode => decode(opts.protocol()?.as_ref(), format),
+        SubCommand::SetState(state) => set_state(opts.protocol()?.as_ref(), format, state),
+        SubCommand::Update {
+            unit,
+            update: state_update,
+            backend,
+        } => update(
+            opts.protocol()?.as_ref(),
+            format,
+            &opts.store(),
+            &unit,
+            state_update,
+            &backend,
+        ),
+        SubCommand::Units => {
+            let states = opts.store().states()?;
+            println!("{}", serde_json::to_string_pretty(&states)?);
+            Ok(())
+        }
+        SubCommand::Special { function } => {
+            send_command(&opts.lennox()?, format, Command::Special(function))
+        }
+        SubCommand::FollowMe(follow_me) => {
+            send_command(&opts.lennox()?, format, Command::FollowMe(follow_me))
+        }
+        SubCommand::Listen => listen(&opts.lennox()?, format),
+        SubCommand::Broadlink => broadlink_decode(format),
+        SubCommand::Irp => irp_decode(format),
+        SubCommand::Detect => detect(opts.protocol()?, format),
+        SubCommand::Calibrate => calibrate(opts.protocol()?.as_ref(), format),
+        SubCommand::SmartIR => smartir::gen_smartir(opts.protocol()?.as_ref()),
+        SubCommand::Lirc => lirc::gen_lircd_conf(opts.protocol()?.as_ref()),
+        SubCommand::Flipper => flipper::gen_ir_file(opts.protocol()?.as_ref()),
+        SubCommand::Esphome => esp::gen_esphome(opts.protocol()?.as_ref()),
+        SubCommand::Irremote => esp::gen_irremote(opts.protocol()?.as_ref()),
+        SubCommand::HaStorage(command) => ha_storage::run(opts.protocol()?.as_ref(), command),
+        SubCommand::Rf(command) => rf(format, command),
+        SubCommand::Discover { timeout } => discover(timeout),
+        SubCommand::Send(device) => send(format, &device, io::stdin().lock(), io::stdout()),
+        SubCommand::Learn { device, count } => learn(format, &device, count),
+        SubCommand::MockDevice {
+            listen,
+            devtype,
+            teach,
+        } => mock_device(opts.protocol()?.as_ref(), format, listen, devtype, teach),
+        SubCommand::Mqtt { mqtt, backend } => mqtt::run(
+            opts.protocol()?.as_ref(),
+            mqtt,
+            &opts.store(),
+            backend.transmitter(format)?.as_mut(),
+        ),
+        SubCommand::Schedule { schedule, .. } if schedule.dry_run => {
+            schedule::dry_run(opts.protocol()?.as_ref(), &opts.store(), &schedule)
+        }
+        SubCommand::Schedule { schedule, backend } => schedule::run(
+            opts.protocol()?.as_ref(),
+            &opts.store(),
+            backend.transmitter(format)?.as_mut(),
+            &schedule,
+        ),
+        SubCommand::Serve { listen, backend } => server::run(
+            opts.protocol()?.as_ref(),
+            listen,
+            &opts.store(),
+            backend.transmitter(format)?.as_mut(),
+        ),
     }
 }
 
-
 #[cfg(test)]
 mod test {
+    use super::*;
+
     #[test]
     fn test() {
         let b = r"JgDKAIyREjQSEhI0EjUTERESETUTERETETQSEhISETUSNBISEjQTNBISEhESNBM0EjQTNBM0EhISNBI0ExESERISEhESERISEhESNBM0EjQTNBISEhESNBM0EhISERIREhISNBI0E6qRkBM0ExESNBI0ExESEhI0EhISERI0EhISERI0EzQSEhE1EjQTERETETUSNBI1ETUSNRIREjUSNRESEhIREhESERMREhESETUSNRI0EjUSEhATETUSNRISERIQFA8TETYQNhEADQUAAAAAAAAAAAAAAAAAAA==";
         let d = base64::decode(b).unwrap();
+        let recording = Recording::from_bytes(Bytes::from(d)).unwrap();
+        assert!(!recording.ticks.is_empty());
+    }
+
+    #[test]
+    fn test_set_state_send() {
+        use crate::state::{Fan, Mode, Temperature};
 
+        let mock = MockDevice::spawn(0x5216).unwrap();
+        let device = DeviceOpts {
+            host: Some(mock.addr().to_string()),
+            mac: None,
+            device_config: None,
+            timeout: 1,
+        };
+
+        // set-state | send
+        let lennox = Lennox::new();
+        let state = ControlState {
+            power: true,
+            mode: Mode::Cool,
+            temperature: Some(Temperature::Celsius(22)),
+            fan: Fan::Max,
+            sleep: false,
+            off_timer: None,
+            on_timer: None,
+        };
+        let code = encode_state(&lennox, Format::Broadlink, &state).unwrap();
+        let mut output = Vec::new();
+        send(Format::Broadlink, &device, code.as_bytes(), &mut output).unwrap();
+        assert_eq!(output, b"Sent\n");
+
+        let received = mock.received();
+        assert_eq!(received.len(), 1);
+        assert_eq!(hex::encode(received[0].to_bytes()), code);
+        assert_eq!(lennox.decode(&received[0].pulses()).unwrap(), state);
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,524 @@
+/** MQTT bridge exposing a heat pump as a Home Assistant climate entity
+ * The climate entity is announced through MQTT discovery. Mode, temperature and fan commands from
+ * Home Assistant are merged into the assumed state of the heat pump, kept in the state store,
+ * encoded with the protocol and transmitted. The assumed state is then published back since heat
+ * pumps don't report theirs, along with the changes other tools make to the stored state.
+ */
+use std::{
+    sync::mpsc::{self, RecvTimeoutError},
+    thread,
+    time::Duration,
+};
+
+use anyhow::bail;
+use clap::Parser;
+use rumqttc::{Client, Event, LastWill, MqttOptions, Packet, QoS};
+use serde_json::json;
+
+use crate::{
+    protocol::{self, Protocol},
+    state::{ControlState, Fan, Mode, StateUpdate, Temperature},
+    store::Store,
+    transmitter::Transmitter,
+};
+
+#[derive(Clone, Parser, Debug)]
+pub struct MqttOpts {
+    /// MQTT broker host
+    #[clap(long, default_value = "localhost")]
+    pub broker: String,
+
+    /// MQTT broker port
+    #[clap(long, default_value = "1883")]
+    pub port: u16,
+
+    /// Username to authenticate with the broker
+    #[clap(long)]
+    pub username: Option<String>,
+
+    /// Password to authenticate with the broker
+    #[clap(long, requires = "username")]
+    pub password: Option<String>,
+
+    /// Prefix Home Assistant reads discovery configurations from
+    #[clap(long, default_value = "homeassistant")]
+    pub discovery_prefix: String,
+
+    /// Topic under which the commands and states of the heat pump are exchanged, unique to each
+    /// heat pump
+    #[clap(long, default_value = "heatpump")]
+    pub base_topic: String,
+
+    /// Name of the climate entity in Home Assistant
+    #[clap(long, default_value = "Heat pump")]
+    pub name: String,
+
+    /// Unit the assumed state is stored under, shared with the update command and the API
+    #[clap(long, default_value = "heatpump")]
+    pub unit: String,
+}
+
+/// Home Assistant climate entity backed by the assumed state of a heat pump
+pub struct Bridge<'a> {
+    protocol: &'a dyn Protocol,
+    opts: MqttOpts,
+    /// Last state sent. The set temperature is kept in fan mode, to be restored in other modes.
+    state: ControlState,
+}
+
+impl<'a> Bridge<'a> {
+    pub fn new(protocol: &'a dyn Protocol, opts: MqttOpts, state: ControlState) -> Self {
+        Self {
+            protocol,
+            opts,
+            state,
+        }
+    }
+
+    fn topic(&self, name: &str) -> String {
+        format!("{}/{}", self.opts.base_topic, name)
+    }
+
+    pub fn availability_topic(&self) -> String {
+        self.topic("availability")
+    }
+
+    /// Topics receiving commands from Home Assistant
+    pub fn command_topics(&self) -> Vec<String> {
+        ["mode/set", "temperature/set", "fan_mode/set"]
+            .iter()
+            .map(|name| self.topic(name))
+            .collect()
+    }
+
+    /// Topic and payload of the discovery configuration
+    pub fn discovery_config(&self) -> (String, serde_json::Value) {
+        let object_id: String = self
+            .opts
+            .base_topic
+            .chars()
+            .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+            .collect();
+        let range = self.protocol.temperature_range();
+
+        let mut modes = vec!["off".to_string()];
+        modes.extend(self.protocol.modes().iter().map(|&mode| ha_mode(mode)));
+
+        let config = json!({
+            "name": self.opts.name,
+            "unique_id": object_id,
+            "modes": modes,
+            "fan_modes": self.protocol.fans().iter().map(|&fan| ha_fan(fan)).collect::<Vec<_>>(),
+            "min_temp": range.start(),
+            "max_temp": range.end(),
+            "temp_step": 1,
+            "precision": 1.0,
+            "temperature_unit": "C",
+            "mode_command_topic": self.topic("mode/set"),
+            "mode_state_topic": self.topic("mode/state"),
+            "temperature_command_topic": self.topic("temperature/set"),
+            "temperature_state_topic": self.topic("temperature/state"),
+            "fan_mode_command_topic": self.topic("fan_mode/set"),
+            "fan_mode_state_topic": self.topic("fan_mode/state"),
+            "availability_topic": self.availability_topic(),
+            "device": {
+                "identifiers": [object_id],
+                "name": self.opts.name,
+                "manufacturer": self.protocol.manufacturer(),
+                "model": self.protocol.models().join(", "),
+            },
+        });
+
+        let topic = format!(
+            "{}/climate/{}/config",
+            self.opts.discovery_prefix, object_id
+        );
+        (topic, config)
+    }
+
+    /// State topics and payloads reporting the assumed state
+    pub fn state_messages(&self) -> Vec<(String, String)> {
+        let mode = match self.state.power {
+            true => ha_mode(self.state.mode),
+            false => "off".into(),
+        };
+        let temperature = self
+            .state
+            .temperature
+            .map(|temperature| temperature.value().to_string())
+            .unwrap_or_default();
+
+        vec![
+            (self.topic("mode/state"), mode),
+            (self.topic("temperature/state"), temperature),
+            (self.topic("fan_mode/state"), ha_fan(self.state.fan)),
+        ]
+    }
+
+    /// Parses a command into the update it makes to the assumed state
+    pub fn command(&self, topic: &str, payload: &[u8]) -> anyhow::Result<StateUpdate> {
+        let payload = std::str::from_utf8(payload)?.trim();
+        let mut update = StateUpdate::default();
+
+        match topic.strip_prefix(&self.opts.base_topic) {
+            Some("/mode/set") if payload == "off" => update.power = Some(false),
+            Some("/mode/set") => {
+                update.mode = self
+                    .protocol
+                    .modes()
+                    .iter()
+                    .copied()
+                    .find(|&mode| ha_mode(mode) == payload);
+                if update.mode.is_none() {
+                    bail!("unsupported mode: {}", payload);
+                }
+                update.power = Some(true);
+            }
+            Some("/temperature/set") => {
+                // Home Assistant sends temperatures as decimals, e.g. 22.0
+                let temperature = payload.parse::<f32>()?.round();
+                let range = self.protocol.temperature_range();
+                if !(*range.start() as f32..=*range.end() as f32).contains(&temperature) {
+                    bail!("temperature out of range: {}", payload);
+                }
+                update.temperature = Some(Temperature::Celsius(temperature as u8));
+            }
+            Some("/fan_mode/set") => {
+                update.fan = self
+                    .protocol
+                    .fans()
+                    .iter()
+                    .copied()
+                    .find(|&fan| ha_fan(fan) == payload);
+                if update.fan.is_none() {
+                    bail!("unsupported fan mode: {}", payload);
+                }
+            }
+            _ => bail!("unknown topic: {}", topic),
+        }
+
+        Ok(update)
+    }
+
+    /// Merges an update into the assumed state, without applying it. Returns the new state, and
+    /// whether it needs to be transmitted: settings changed while the heat pump is off are only
+    /// sent with the next power change.
+    pub fn merge(&self, state: ControlState, update: &StateUpdate) -> (ControlState, bool) {
+        let merged = update.apply(state);
+        (merged, merged.power || update.power.is_some())
+    }
+
+    /// Encodes a state into pulses. Fan mode doesn't carry a set temperature.
+    pub fn encode(&self, state: &ControlState) -> anyhow::Result<Vec<Duration>> {
+        let mut state = *state;
+        if state.mode == Mode::Fan {
+            state.temperature = None;
+        }
+        self.protocol.encode(&state)
+    }
+
+    pub fn set_state(&mut self, state: ControlState) {
+        self.state = state;
+    }
+}
+
+/// Mode name used by Home Assistant
+fn ha_mode(mode: Mode) -> String {
+    match mode {
+        Mode::Fan => "fan_only".into(),
+        _ => mode.as_ref().to_lowercase(),
+    }
+}
+
+/// Fan mode name used by Home Assistant
+fn ha_fan(fan: Fan) -> String {
+    match fan {
+        Fan::Min => "low",
+        Fan::Medium => "medium",
+        Fan::Max => "high",
+        Fan::Auto => "auto",
+        Fan::Zero => "off",
+    }
+    .into()
+}
+
+/// Interval at which the store is read for changes made by other tools
+const STORE_POLL: Duration = Duration::from_secs(1);
+
+/// Runs the bridge until interrupted, reconnecting to the broker when the connection is lost
+pub fn run(
+    protocol: &dyn Protocol,
+    opts: MqttOpts,
+    store: &Store,
+    transmitter: &mut dyn Transmitter,
+) -> anyhow::Result<()> {
+    let initial = protocol::initial_state(protocol);
+    let mut bridge = Bridge::new(protocol, opts.clone(), store.state(&opts.unit, initial)?);
+
+    let mut options = MqttOptions::new(
+        format!("heatpump-{}", std::process::id()),
+        &opts.broker,
+        opts.port,
+    );
+    options.set_keep_alive(Duration::from_secs(30));
+    options.set_last_will(LastWill::new(
+        bridge.availability_topic(),
+        "offline",
+        QoS::AtLeastOnce,
+        true,
+    ));
+    if let Some(username) = &opts.username {
+        options.set_credentials(username, opts.password.clone().unwrap_or_default());
+    }
+
+    let (client, mut connection) = Client::new(options, 16);
+
+    // Events are read on their own thread, so that the store can be checked between them
+    let (sender, events) = mpsc::channel();
+    thread::spawn(move || {
+        for event in connection.iter() {
+            let failed = event.is_err();
+            if sender.send(event).is_err() {
+                break;
+            }
+            if failed {
+                thread::sleep(Duration::from_secs(5));
+            }
+        }
+    });
+
+    let mut connected = false;
+    loop {
+        let event = match events.recv_timeout(STORE_POLL) {
+            Ok(event) => event,
+            // Publishes the changes made by other tools, e.g. update, serve or schedule
+            Err(RecvTimeoutError::Timeout) => {
+                match store.state(&opts.unit, initial) {
+                    Ok(state) if connected && state != bridge.state => {
+                        bridge.set_state(state);
+                        publish_state(&client, &bridge)?;
+                    }
+                    Ok(_) => {}
+                    Err(e) => eprintln!("Error: {:#}", e),
+                }
+                continue;
+            }
+            Err(RecvTimeoutError::Disconnected) => break,
+        };
+
+        match event {
+            // Everything is announced again after reconnecting, in case the broker lost it
+            Ok(Event::Incoming(Packet::ConnAck(_))) => {
+                eprintln!("Connected to {}:{}", opts.broker, opts.port);
+                connected = true;
+                // Subscribing first, so that commands sent in reply to the state aren't missed
+                for topic in bridge.command_topics() {
+                    client.subscribe(topic, QoS::AtLeastOnce)?;
+                }
+                // Other tools may have changed the state while disconnected
+                bridge.set_state(store.state(&opts.unit, initial)?);
+                let (topic, config) = bridge.discovery_config();
+                client.publish(topic, QoS::AtLeastOnce, true, config.to_string())?;
+                client.publish(
+                    bridge.availability_topic(),
+                    QoS::AtLeastOnce,
+                    true,
+                    "online",
+                )?;
+                publish_state(&client, &bridge)?;
+            }
+            Ok(Event::Incoming(Packet::Publish(publish))) => {
+                // The update is merged into the stored state, which other tools may have changed
+                let result = bridge
+                    .command(&publish.topic, &publish.payload)
+                    .and_then(|update| {
+                        store.update(&opts.unit, initial, |state| {
+                            let (state, transmit) = bridge.merge(state, &update);
+                            if transmit {
+                                let pulses = bridge.encode(&state)?;
+                                transmitter.transmit(protocol.carrier_frequency(), pulses)?;
+                            }
+                            Ok(state)
+                        })
+                    });
+
+                match result {
+                    Ok(state) => {
+                        eprintln!("{}: {:?}", publish.topic, state);
+                        bridge.set_state(state);
+                    }
+                    Err(e) => {
+                        eprintln!("{}: Error: {:#}", publish.topic, e);
+                        bridge.set_state(store.state(&opts.unit, initial)?);
+                    }
+                }
+                // Home Assistant reverts to the published state when the command failed
+                publish_state(&client, &bridge)?;
+            }
+            Ok(_) => {}
+            Err(e) => {
+                eprintln!("Connection error: {}", e);
+                connected = false;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn publish_state(client: &Client, bridge: &Bridge) -> anyhow::Result<()> {
+    for (topic, payload) in bridge.state_messages() {
+        client.publish(topic, QoS::AtLeastOnce, true, payload)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{lennox::Lennox, store::TempStore};
+
+    fn opts() -> MqttOpts {
+        MqttOpts::parse_from(["mqtt", "--base-topic", "heatpump/bedroom"])
+    }
+
+    #[test]
+    fn test_discovery_config() {
+        let lennox = Lennox::new();
+        let bridge = Bridge::new(&lennox, opts(), protocol::initial_state(&lennox));
+
+        let (topic, config) = bridge.discovery_config();
+        assert_eq!(topic, "homeassistant/climate/heatpump_bedroom/config");
+        assert_eq!(
+            config["modes"],
+            json!(["off", "auto", "cool", "dry", "heat", "fan_only"])
+        );
+        assert_eq!(
+            config["fan_modes"],
+            json!(["low", "medium", "high", "auto"])
+        );
+        assert_eq!(config["min_temp"], 17);
+        assert_eq!(config["max_temp"], 30);
+        assert_eq!(config["mode_command_topic"], "heatpump/bedroom/mode/set");
+    }
+
+    #[test]
+    fn test_command() {
+        let lennox = Lennox::new();
+        let mut bridge = Bridge::new(&lennox, opts(), protocol::initial_state(&lennox));
+        let mut command = |topic: &str, payload: &[u8]| {
+            let update = bridge.command(topic, payload)?;
+            let (state, transmit) = bridge.merge(bridge.state, &update);
+            bridge.set_state(state);
+            anyhow::Ok((state, transmit))
+        };
+
+        // Settings changed while off are kept for the next mode command
+        let (_, transmit) = command("heatpump/bedroom/temperature/set", b"24.0").unwrap();
+        assert!(!transmit);
+
+        let (state, transmit) = command("heatpump/bedroom/mode/set", b"heat").unwrap();
+        assert!(transmit);
+        assert!(state.power);
+        assert_eq!(state.mode, Mode::Heat);
+        assert_eq!(state.temperature, Some(Temperature::Celsius(24)));
+
+        let (state, _) = command("heatpump/bedroom/fan_mode/set", b"high").unwrap();
+        assert_eq!(state.fan, Fan::Max);
+
+        // Rejected commands leave the state as it was
+        assert!(command("heatpump/bedroom/temperature/set", b"31").is_err());
+        assert!(command("heatpump/bedroom/mode/set", b"eco").is_err());
+        assert_eq!(bridge.state, state);
+        assert_eq!(
+            lennox.decode(&bridge.encode(&state).unwrap()).unwrap(),
+            state
+        );
+
+        let update = bridge.command("heatpump/bedroom/mode/set", b"off").unwrap();
+        let (state, transmit) = bridge.merge(bridge.state, &update);
+        assert!(transmit);
+        bridge.set_state(state);
+        assert_eq!(
+            bridge.state_messages(),
+            [
+                ("heatpump/bedroom/mode/state".into(), "off".into()),
+                ("heatpump/bedroom/temperature/state".into(), "24".into()),
+                ("heatpump/bedroom/fan_mode/state".into(), "high".into()),
+            ]
+        );
+    }
+
+    /// Runs against a broker on MQTT_BROKER (default localhost), e.g. `mosquitto -p 1883`
+    #[test]
+    #[ignore]
+    fn test_broker() {
+        use std::sync::mpsc;
+
+        let broker = std::env::var("MQTT_BROKER").unwrap_or_else(|_| "localhost".into());
+        let mut opts = opts();
+        opts.broker = broker.clone();
+        opts.base_topic = format!("heatpump-test-{}", std::process::id());
+        let base_topic = opts.base_topic.clone();
+        let store = TempStore::new("mqtt");
+        let bridge_store = store.open();
+        let unit = opts.unit.clone();
+
+        let (sender, received) = mpsc::channel();
+        thread::spawn(move || {
+            let lennox = Lennox::new();
+            let mut transmitter = |_, pulses: Vec<Duration>| {
+                sender.send(lennox.decode(&pulses)?)?;
+                Ok(())
+            };
+            run(&Lennox::new(), opts, &bridge_store, &mut transmitter).unwrap();
+        });
+
+        let (client, mut connection) =
+            Client::new(MqttOptions::new("heatpump-test-client", broker, 1883), 16);
+        client
+            .subscribe(format!("{}/mode/state", base_topic), QoS::AtLeastOnce)
+            .unwrap();
+
+        // The bridge subscribes before publishing its initial state, then publishes the assumed
+        // state back once the command is transmitted, and the changes made by other tools
+        let deadline = std::time::Instant::now() + Duration::from_secs(10);
+        loop {
+            let remaining = deadline
+                .checked_duration_since(std::time::Instant::now())
+                .expect("timed out waiting for the bridge");
+            let event = connection
+                .recv_timeout(remaining)
+                .expect("timed out waiting for the bridge");
+            if let Event::Incoming(Packet::Publish(publish)) = event.unwrap() {
+                match &publish.payload[..] {
+                    b"off" => client
+                        .publish(
+                            format!("{}/mode/set", base_topic),
+                            QoS::AtLeastOnce,
+                            false,
+                            "cool",
+                        )
+                        .unwrap(),
+                    b"cool" => {
+                        let initial = protocol::initial_state(&Lennox::new());
+                        store
+                            .update(&unit, initial, |state| {
+                                anyhow::Ok(ControlState {
+                                    mode: Mode::Heat,
+                                    ..state
+                                })
+                            })
+                            .unwrap();
+                    }
+                    b"heat" => break,
+                    _ => {}
+                }
+            }
+        }
+
+        let state = received.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert!(state.power);
+        assert_eq!(state.mode, Mode::Cool);
+    }
+}
@@ -0,0 +1,364 @@
+/** Fixed-code OOK decoding/encoding for 315/433MHz remotes using PT2262/EV1527-style encoders
+ * Each bit is a high pulse followed by a low pulse: 1 unit high and 3 units low for 0, 3 units
+ * high and 1 unit low for 1. Frames are delimited by a 1 unit high followed by a 31 units low sync,
+ * sent before the frame by EV1527 and after it by PT2262. Remotes repeat frames while pressed.
+ * EV1527 frames are 24 bits: a 20 bit address followed by 4 data bits. PT2262 frames are 12
+ * tri-state symbols of two bits each: 00 for 0, 11 for 1, and 01 for floating (F).
+ */
+use std::{collections::HashMap, fmt, time::Duration};
+
+use clap::Parser;
+use thiserror::Error;
+
+use crate::{
+    broadlink::Recording,
+    pwm::{Codec, Rule},
+};
+
+#[derive(Clone, Parser, Debug)]
+pub enum RfCommand {
+    /// Decode the fixed codes sent by RF captures read from stdin
+    Decode,
+
+    /// Encode a fixed code into an RF broadlink code
+    Encode {
+        /// Code as hexadecimal, first transmitted bit being the most significant
+        #[clap(
+            long,
+            required_unless_present = "tristate",
+            conflicts_with = "tristate"
+        )]
+        code: Option<String>,
+
+        /// Number of bits of the code
+        #[clap(long, default_value = "24")]
+        bits: usize,
+
+        /// PT2262 tri-state symbols (0, 1 or F), instead of --code
+        #[clap(long)]
+        tristate: Option<String>,
+
+        /// Duration of a unit, in microseconds
+        #[clap(long, default_value = "350")]
+        unit: u64,
+
+        /// Frequency band, in MHz: 433 or 315
+        #[clap(long, default_value = "433")]
+        band: u16,
+
+        /// Frames in each transmission
+        #[clap(long, default_value = "4")]
+        frames: usize,
+
+        /// Additional transmissions made by the device
+        #[clap(long, default_value = "0")]
+        repeat: u8,
+    },
+}
+
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum OokError {
+    #[error("not enough pulses to estimate the timing unit")]
+    TooShort,
+    #[error("no complete frame found")]
+    NoFrame,
+    #[error("frames are at most 64 bits long, got {0}")]
+    TooLong(usize),
+    #[error("invalid tri-state symbol: {0}")]
+    InvalidSymbol(char),
+    #[error("invalid code: {0}")]
+    InvalidCode(String),
+}
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
+pub enum OokPulse {
+    Short,
+    Long,
+    Sync,
+}
+
+/// Bits of a frame, the first transmitted bit being the most significant
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Frame {
+    pub bits: u64,
+    pub len: usize,
+}
+
+impl Frame {
+    pub fn new(bits: u64, len: usize) -> Result<Self, OokError> {
+        match len {
+            0..=64 => Ok(Self { bits, len }),
+            _ => Err(OokError::TooLong(len)),
+        }
+    }
+
+    pub fn from_hex(code: &str, len: usize) -> Result<Self, OokError> {
+        let code = code.trim_start_matches("0x");
+        let bits = u64::from_str_radix(code, 16).map_err(|_| OokError::InvalidCode(code.into()))?;
+        if len < 64 && bits >> len != 0 {
+            return Err(OokError::InvalidCode(code.into()));
+        }
+        Frame::new(bits, len)
+    }
+
+    pub fn from_tristate(symbols: &str) -> Result<Self, OokError> {
+        let mut bits = 0;
+        for symbol in symbols.chars() {
+            let pair = match symbol {
+                '0' => 0b00,
+                '1' => 0b11,
+                'F' | 'f' => 0b01,
+                _ => return Err(OokError::InvalidSymbol(symbol)),
+            };
+            bits = bits << 2 | pair;
+        }
+        Frame::new(bits, symbols.len() * 2)
+    }
+
+    /// Bits in transmission order
+    pub fn iter(&self) -> impl Iterator<Item = bool> + '_ {
+        (0..self.len).rev().map(|i| self.bits >> i & 1 == 1)
+    }
+
+    /// PT2262 tri-state symbols, when the frame can be read as such
+    pub fn tristate(&self) -> Option<String> {
+        if !self.len.is_multiple_of(2) {
+            return None;
+        }
+
+        (0..self.len / 2)
+            .rev()
+            .map(|i| match self.bits >> (i * 2) & 0b11 {
+                0b00 => Some('0'),
+                0b11 => Some('1'),
+                0b01 => Some('F'),
+                _ => None,
+            })
+            .collect()
+    }
+}
+
+impl fmt::Display for Frame {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:0width$x} ({} bits)",
+            self.bits,
+            self.len,
+            width = self.len.div_ceil(4)
+        )
+    }
+}
+
+/// Most common frame of a capture
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Decoded {
+    pub frame: Frame,
+    /// Number of times the frame was received
+    pub count: usize,
+}
+
+pub struct Ook {
+    codec: Codec<OokPulse>,
+}
+
+impl Ook {
+    const LONG_UNITS: u32 = 3;
+    const SYNC_UNITS: u32 = 31;
+
+    pub fn new(unit: Duration) -> Self {
+        // Cheap encoders drift a lot, use tolerances as wide as the ratios allow
+        let rule = |units: u32, percent: u32| Rule {
+            duration: unit * units,
+            tolerance: unit * units * percent / 100,
+        };
+
+        Self {
+            codec: Codec::new(
+                [
+                    (OokPulse::Short, rule(1, 50)),
+                    (OokPulse::Long, rule(Ook::LONG_UNITS, 40)),
+                    (OokPulse::Sync, rule(Ook::SYNC_UNITS, 30)),
+                ]
+                .into_iter(),
+            ),
+        }
+    }
+
+    /// Estimates the timings of a capture. Every bit has a short pulse, so about half of the
+    /// pulses last one unit, and the rules are then fitted to the capture.
+    pub fn estimate(pulses: &[Duration]) -> Result<Self, OokError> {
+        let mut sorted = pulses.to_vec();
+        sorted.sort();
+        if sorted.len() < 8 {
+            return Err(OokError::TooShort);
+        }
+
+        let nominal = Ook::new(sorted[sorted.len() / 4]);
+        Ok(Self {
+            codec: nominal.codec.calibrate(pulses.iter().copied()),
+        })
+    }
+
+    /// Duration of a unit
+    pub fn unit(&self) -> Duration {
+        self.pulse(OokPulse::Short)
+    }
+
+    fn pulse(&self, pulse: OokPulse) -> Duration {
+        self.codec.encode_pulse(pulse).unwrap()
+    }
+
+    /// Splits pulses into frames at sync pulses and longer silences. Pulse pairs that aren't a bit
+    /// drop the frame they're in.
+    pub fn frames(&self, pulses: &[Duration]) -> Vec<Frame> {
+        let separator = self.pulse(OokPulse::Long) * 2;
+        let mut frames = Vec::new();
+        let mut current = Some(Frame { bits: 0, len: 0 });
+
+        for pair in pulses.chunks(2) {
+            let high = self.codec.decode_pulse(pair[0]).ok();
+            let low = pair.get(1).copied();
+
+            if low.is_none_or(|low| low > separator) {
+                if let Some(frame) = current.filter(|f| f.len > 0) {
+                    frames.push(frame);
+                }
+                current = Some(Frame { bits: 0, len: 0 });
+                continue;
+            }
+
+            let bit = match (high, low.and_then(|l| self.codec.decode_pulse(l).ok())) {
+                (Some(OokPulse::Short), Some(OokPulse::Long)) => Some(false),
+                (Some(OokPulse::Long), Some(OokPulse::Short)) => Some(true),
+                _ => None,
+            };
+
+            current = match (current, bit) {
+                (Some(frame), Some(bit)) if frame.len < 64 => Some(Frame {
+                    bits: frame.bits << 1 | bit as u64,
+                    len: frame.len + 1,
+                }),
+                _ => None,
+            };
+        }
+
+        frames
+    }
+
+    /// Finds the most common frame, preferring longer ones when several are as common
+    pub fn decode(&self, pulses: &[Duration]) -> Result<Decoded, OokError> {
+        let mut counts: HashMap<Frame, usize> = HashMap::new();
+        for frame in self.frames(pulses) {
+            *counts.entry(frame).or_default() += 1;
+        }
+
+        counts
+            .into_iter()
+            .max_by_key(|&(frame, count)| (count, frame.len, frame.bits))
+            .map(|(frame, count)| Decoded { frame, count })
+            .ok_or(OokError::NoFrame)
+    }
+
+    /// Encodes a frame sent `frames` times, with a sync on both sides of each frame
+    pub fn encode(&self, frame: &Frame, frames: usize) -> Vec<Duration> {
+        let (short, long) = (self.pulse(OokPulse::Short), self.pulse(OokPulse::Long));
+        let sync = [short, self.pulse(OokPulse::Sync)];
+
+        let mut pulses = sync.to_vec();
+        for _ in 0..frames {
+            for bit in frame.iter() {
+                pulses.extend(if bit { [long, short] } else { [short, long] });
+            }
+            pulses.extend(sync);
+        }
+        pulses
+    }
+}
+
+/// Decodes an RF recording into a human readable description
+pub fn describe(recording: &Recording) -> anyhow::Result<String> {
+    // Frames repeated by the device count as much as frames repeated within the recording
+    let pulses = recording.transmitted_pulses();
+    let ook = Ook::estimate(&pulses)?;
+    let decoded = ook.decode(&pulses)?;
+
+    let mut description = format!(
+        "{:?} x{}: {}, unit {}us, {} frames",
+        recording.transport,
+        recording.transmissions(),
+        decoded.frame,
+        ook.unit().as_micros(),
+        decoded.count
+    );
+    if let Some(tristate) = decoded.frame.tristate() {
+        description += &format!("\nTri-state: {}", tristate);
+    }
+
+    Ok(description)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::broadlink::Transport;
+
+    #[test]
+    fn test_frame() {
+        let frame = Frame::from_tristate("0F1F").unwrap();
+        assert_eq!(
+            frame,
+            Frame {
+                bits: 0b00011101,
+                len: 8
+            }
+        );
+        assert_eq!(frame.tristate().unwrap(), "0F1F");
+        assert_eq!(frame.to_string(), "1d (8 bits)");
+
+        // 10 isn't a tri-state symbol
+        assert_eq!(Frame::from_hex("2", 2).unwrap().tristate(), None);
+        assert!(Frame::from_hex("1ff", 8).is_err());
+        assert_eq!(
+            Frame::from_tristate("0X"),
+            Err(OokError::InvalidSymbol('X'))
+        );
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let frame = Frame::from_hex("a5c3e1", 24).unwrap();
+        let pulses = Ook::new(Duration::from_micros(320)).encode(&frame, 5);
+
+        // Transmitted over RF, with drifting timings and a first frame cut by the receiver's AGC
+        let recording = Recording::new_rf433(pulses[10..].to_vec(), 0);
+        let received: Vec<_> = recording
+            .pulses()
+            .iter()
+            .enumerate()
+            .map(|(i, &p)| if i % 3 == 0 { p * 11 / 10 } else { p })
+            .collect();
+
+        let ook = Ook::estimate(&received).unwrap();
+        let unit = ook.unit().as_micros();
+        assert!((300..=360).contains(&unit), "unit {}", unit);
+
+        let decoded = ook.decode(&received).unwrap();
+        assert_eq!(decoded.frame, frame);
+        assert_eq!(decoded.count, 4);
+        assert_eq!(ook.decode(&ook.encode(&frame, 2)).unwrap().frame, frame);
+    }
+
+    #[test]
+    fn test_describe_pt2262() {
+        let frame = Frame::from_tristate("0FF0F0FF1001").unwrap();
+        let pulses = Ook::new(Duration::from_micros(400)).encode(&frame, 3);
+        let recording = Recording::new_rf315(pulses, 1);
+        assert_eq!(recording.transport, Transport::Rf315);
+
+        let description = describe(&recording).unwrap();
+        // The unit is rounded to broadlink ticks
+        assert!(description.starts_with("Rf315 x2: 1445c3 (24 bits), unit 395us, 6 frames"));
+        assert!(description.ends_with("Tri-state: 0FF0F0FF1001"));
+    }
+}
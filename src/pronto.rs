@@ -0,0 +1,212 @@
+/** Encoding/decoding of Pronto Hex codes, as used by universal remotes and GlobalCache devices
+ * Only the learned format (leading 0000 word) is supported, durations are expressed in carrier periods.
+ * Format description: http://www.remotecentral.com/features/irdisp2.htm
+ */
+use std::{fmt, str::FromStr, time::Duration};
+
+use thiserror::Error;
+
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum ProntoError {
+    #[error("invalid hex word: {0}")]
+    InvalidWord(String),
+    #[error("unsupported pronto format: {0:04X}, only learned codes (0000) are supported")]
+    UnsupportedFormat(u16),
+    #[error("truncated pronto code")]
+    Truncated,
+    #[error("the header announces {expected} durations but the code holds {actual}")]
+    LengthMismatch { expected: usize, actual: usize },
+    #[error("invalid carrier frequency")]
+    InvalidFrequency,
+}
+
+/// A Pronto code: a sequence sent once, followed by a sequence repeated for as long as the button
+/// is held. Both are on-off burst pairs.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Pronto {
+    /// Carrier frequency, in Hz
+    pub frequency: u32,
+    pub intro: Vec<Duration>,
+    pub repeat: Vec<Duration>,
+}
+
+impl Pronto {
+    const LEARNED: u16 = 0x0000;
+
+    /// Pronto clock period in microseconds, the frequency word counts carrier periods in these units
+    const CLOCK_PERIOD_US: f64 = 0.241246;
+
+    /// Appended to sequences with an odd number of pulses, so that they end on a full burst pair
+    const TRAILING_GAP: Duration = Duration::from_millis(100);
+
+    /// Builds a code sent once, without any repeat sequence
+    pub fn new(frequency: u32, pulses: Vec<Duration>) -> Self {
+        Self {
+            frequency,
+            intro: pulses,
+            repeat: Vec::new(),
+        }
+    }
+
+    /// On-off pulse durations of the intro sequence, followed by one repeat sequence
+    pub fn pulses(&self) -> Vec<Duration> {
+        self.intro.iter().chain(&self.repeat).copied().collect()
+    }
+
+    fn frequency_word(&self) -> u16 {
+        (1_000_000.0 / (self.frequency as f64 * Pronto::CLOCK_PERIOD_US)).round() as u16
+    }
+
+    /// Duration of a single carrier period, as represented by the frequency word
+    fn carrier_period_us(frequency_word: u16) -> f64 {
+        frequency_word as f64 * Pronto::CLOCK_PERIOD_US
+    }
+
+    fn burst_pairs(pulses: &[Duration]) -> Vec<Duration> {
+        let mut pulses = pulses.to_vec();
+        if pulses.len() % 2 == 1 {
+            pulses.push(Pronto::TRAILING_GAP);
+        }
+        pulses
+    }
+}
+
+impl fmt::Display for Pronto {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let frequency_word = self.frequency_word();
+        let period = Pronto::carrier_period_us(frequency_word);
+        let intro = Pronto::burst_pairs(&self.intro);
+        let repeat = Pronto::burst_pairs(&self.repeat);
+
+        let mut words = vec![
+            Pronto::LEARNED,
+            frequency_word,
+            (intro.len() / 2) as u16,
+            (repeat.len() / 2) as u16,
+        ];
+        words.extend(intro.iter().chain(&repeat).map(|pulse| {
+            let cycles = (pulse.as_nanos() as f64 / 1000.0 / period).round();
+            cycles.clamp(1.0, u16::MAX as f64) as u16
+        }));
+
+        let words: Vec<_> = words.iter().map(|w| format!("{:04X}", w)).collect();
+        write!(f, "{}", words.join(" "))
+    }
+}
+
+impl FromStr for Pronto {
+    type Err = ProntoError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let words = s
+            .split_whitespace()
+            .map(|w| u16::from_str_radix(w, 16).map_err(|_| ProntoError::InvalidWord(w.into())))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let (header, durations) = match words.len() {
+            n if n >= 4 => words.split_at(4),
+            _ => return Err(ProntoError::Truncated),
+        };
+
+        let (format, frequency_word) = (header[0], header[1]);
+        let (intro_pairs, repeat_pairs) = (header[2] as usize, header[3] as usize);
+
+        if format != Pronto::LEARNED {
+            return Err(ProntoError::UnsupportedFormat(format));
+        }
+
+        if frequency_word == 0 {
+            return Err(ProntoError::InvalidFrequency);
+        }
+
+        let expected = 2 * (intro_pairs + repeat_pairs);
+        if durations.len() != expected {
+            return Err(ProntoError::LengthMismatch {
+                expected,
+                actual: durations.len(),
+            });
+        }
+
+        let period = Pronto::carrier_period_us(frequency_word);
+        let durations: Vec<_> = durations
+            .iter()
+            .map(|&cycles| Duration::from_nanos((cycles as f64 * period * 1000.0).round() as u64))
+            .collect();
+        let (intro, repeat) = durations.split_at(2 * intro_pairs);
+
+        Ok(Pronto {
+            frequency: (1_000_000.0 / period).round() as u32,
+            intro: intro.to_vec(),
+            repeat: repeat.to_vec(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    // NEC code, followed by the NEC repeat burst
+    const NEC: &str = "0000 006D 0022 0002 0157 00AC 0015 0016 0015 0016 0015 0041 0015 0016 0015 0016 0015 0016 0015 0016 0015 0016 0015 0041 0015 0041 0015 0016 0015 0041 0015 0041 0015 0041 0015 0041 0015 0041 0015 0016 0015 0016 0015 0016 0015 0041 0015 0016 0015 0016 0015 0016 0015 0016 0015 0041 0015 0041 0015 0041 0015 0016 0015 0041 0015 0041 0015 0041 0015 0041 0015 0689 0157 0056 0015 0E94";
+
+    #[test]
+    fn test_decode() {
+        let pronto: Pronto = NEC.parse().unwrap();
+        assert_eq!(pronto.frequency, 38029);
+        assert_eq!(pronto.intro.len(), 0x22 * 2);
+        assert_eq!(pronto.repeat.len(), 2 * 2);
+
+        // 0x157 periods of 26.3µs: the 9ms NEC header
+        let header = pronto.intro[0].as_micros();
+        assert!((8950..9050).contains(&header), "{}", header);
+
+        assert_eq!(
+            pronto.pulses().len(),
+            pronto.intro.len() + pronto.repeat.len()
+        );
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let pronto: Pronto = NEC.parse().unwrap();
+        assert_eq!(pronto.to_string(), NEC);
+
+        let pulses = [9000, 4500, 560, 560, 560, 1690, 560].map(Duration::from_micros);
+        let pronto = Pronto::new(38000, pulses.to_vec());
+        let decoded: Pronto = pronto.to_string().parse().unwrap();
+        assert_eq!(
+            decoded.intro.len(),
+            8,
+            "odd sequences are padded with a gap"
+        );
+        assert!(decoded.repeat.is_empty());
+        for (decoded, pulse) in decoded.intro.iter().zip(pulses) {
+            // Durations are rounded to a carrier period
+            assert!(decoded.abs_diff(pulse) < Duration::from_micros(14));
+        }
+    }
+
+    #[test]
+    fn test_errors() {
+        assert_eq!(
+            "0100 006D 0000 0000".parse::<Pronto>(),
+            Err(ProntoError::UnsupportedFormat(0x0100))
+        );
+        assert_eq!("0000 006D".parse::<Pronto>(), Err(ProntoError::Truncated));
+        assert_eq!(
+            "0000 006D 0001 0000 0010".parse::<Pronto>(),
+            Err(ProntoError::LengthMismatch {
+                expected: 2,
+                actual: 1
+            })
+        );
+        assert_eq!(
+            "0000 0000 0000 0000".parse::<Pronto>(),
+            Err(ProntoError::InvalidFrequency)
+        );
+        assert!(matches!(
+            "0000 zz".parse::<Pronto>(),
+            Err(ProntoError::InvalidWord(_))
+        ));
+    }
+}
@@ -0,0 +1,217 @@
+use std::{ops::RangeInclusive, time::Duration};
+
+use anyhow::{anyhow, bail};
+
+use crate::{
+    lennox::Lennox,
+    state::{ControlState, Fan, Mode, Temperature},
+};
+
+/// An HVAC IR protocol, converting complete heat pump states to and from on-off pulse durations
+pub trait Protocol {
+    /// Name used to select the protocol from the command line
+    fn name(&self) -> &'static str;
+
+    fn manufacturer(&self) -> &'static str;
+
+    /// Unit and remote models known to use this protocol
+    fn models(&self) -> &'static [&'static str];
+
+    /// Operating modes that can be selected
+    fn modes(&self) -> &'static [Mode];
+
+    /// Fan speeds that can be selected
+    fn fans(&self) -> &'static [Fan];
+
+    /// Range of set temperatures, in Celsius
+    fn temperature_range(&self) -> RangeInclusive<u8>;
+
+    /// IR carrier frequency, in Hz
+    fn carrier_frequency(&self) -> u32 {
+        38_000
+    }
+
+    /// Whether the protocol carries the sleep flag of ControlState
+    fn supports_sleep(&self) -> bool {
+        false
+    }
+
+    fn encode(&self, state: &ControlState) -> anyhow::Result<Vec<Duration>>;
+
+    fn decode(&self, pulses: &[Duration]) -> anyhow::Result<ControlState>;
+
+    /// Decodes pulses into a human readable description. Protocols with commands other than
+    /// complete states can override this to describe those as well.
+    fn describe(&self, pulses: &[Duration]) -> anyhow::Result<String> {
+        Ok(format!("Decode: {:?}", self.decode(pulses)?))
+    }
+
+    /// How closely pulse timings match the protocol's nominal timings, from 0.0 to 1.0
+    fn confidence(&self, pulses: &[Duration]) -> f32;
+
+    /// Fits the protocol's pulse timings to captured pulses, returning a timing configuration that
+    /// can be saved and loaded back with `set_timings`
+    fn calibrate(&self, _pulses: &[Duration]) -> anyhow::Result<serde_json::Value> {
+        bail!("{} doesn't support timing calibration", self.name())
+    }
+
+    /// Replaces the protocol's pulse timings with a configuration produced by `calibrate`
+    fn set_timings(&mut self, _timings: serde_json::Value) -> anyhow::Result<()> {
+        bail!("{} doesn't support timing calibration", self.name())
+    }
+
+    /// When enabled, transmissions that fail to decode are reconstructed from the protocol's
+    /// redundancy, at the risk of accepting a corrupted message
+    fn set_recovery(&mut self, _enabled: bool) -> anyhow::Result<()> {
+        bail!("{} doesn't support error recovery", self.name())
+    }
+}
+
+/// Returns every known protocol. New vendors only need to be added here.
+pub fn all() -> Vec<Box<dyn Protocol>> {
+    vec![Box::new(Lennox::new())]
+}
+
+/// Looks up a protocol by its name
+pub fn by_name(name: &str) -> anyhow::Result<Box<dyn Protocol>> {
+    let protocols = all();
+    let names: Vec<_> = protocols.iter().map(|p| p.name()).collect();
+    let names = names.join(", ");
+
+    protocols
+        .into_iter()
+        .find(|p| p.name().eq_ignore_ascii_case(name))
+        .ok_or_else(|| anyhow!("unknown protocol: {} (available: {})", name, names))
+}
+
+/// State assumed for a unit nothing was sent to yet: off, with the settings a remote starts with
+pub fn initial_state(protocol: &dyn Protocol) -> ControlState {
+    const TEMPERATURE: u8 = 22;
+
+    let range = protocol.temperature_range();
+    let fan = match protocol.fans().contains(&Fan::Auto) {
+        true => Fan::Auto,
+        false => protocol.fans()[0],
+    };
+
+    ControlState {
+        power: false,
+        mode: protocol.modes()[0],
+        temperature: Some(Temperature::Celsius(
+            TEMPERATURE.clamp(*range.start(), *range.end()),
+        )),
+        fan,
+        sleep: false,
+        off_timer: None,
+        on_timer: None,
+    }
+}
+
+/// Every state the protocol can encode, named after its settings (e.g. `heat_auto_24`,
+/// `sleep_cool_max_22`, `fan_min` and `off`)
+pub fn named_states(protocol: &dyn Protocol) -> Vec<(String, ControlState)> {
+    let state = |mode, fan, temperature, sleep| ControlState {
+        power: true,
+        mode,
+        fan,
+        temperature,
+        sleep,
+        off_timer: None,
+        on_timer: None,
+    };
+
+    // Off still carries a set point, as the remote's own off command does
+    let mut states = vec![("off".to_string(), initial_state(protocol))];
+
+    let sleep_presets: &[bool] = if protocol.supports_sleep() {
+        &[false, true]
+    } else {
+        &[false]
+    };
+
+    for &sleep in sleep_presets {
+        let prefix = if sleep { "sleep_" } else { "" };
+
+        for &mode in protocol.modes() {
+            // Sleep only applies to modes that regulate temperature
+            if sleep && matches!(mode, Mode::Fan | Mode::Dry) {
+                continue;
+            }
+
+            let mode_name = mode.as_ref().to_lowercase();
+            for &fan in protocol.fans() {
+                let fan_name = fan.as_ref().to_lowercase();
+
+                if mode == Mode::Fan {
+                    let name = format!("{}{}_{}", prefix, mode_name, fan_name);
+                    states.push((name, state(mode, fan, None, sleep)));
+                    continue;
+                }
+
+                for temperature in protocol.temperature_range() {
+                    let name = format!("{}{}_{}_{}", prefix, mode_name, fan_name, temperature);
+                    let temperature = Some(Temperature::Celsius(temperature));
+                    states.push((name, state(mode, fan, temperature, sleep)));
+                }
+            }
+        }
+    }
+
+    states
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_by_name() {
+        assert_eq!(by_name("lennox").unwrap().name(), "lennox");
+        assert_eq!(by_name("Lennox").unwrap().name(), "lennox");
+        assert!(by_name("acme").is_err());
+    }
+
+    #[test]
+    fn test_named_states() {
+        let states = named_states(&Lennox::new());
+        let mut names: Vec<_> = states.iter().map(|(n, _)| n.as_str()).collect();
+        assert_eq!(names[0], "off");
+        assert!(names.contains(&"heat_auto_24"));
+
+        names.sort();
+        names.dedup();
+        assert_eq!(names.len(), states.len());
+    }
+
+    #[test]
+    fn test_round_trip() {
+        for protocol in all() {
+            for &mode in protocol.modes() {
+                for &fan in protocol.fans() {
+                    let state = ControlState {
+                        power: true,
+                        mode,
+                        temperature: Some(Temperature::Celsius(
+                            *protocol.temperature_range().start(),
+                        )),
+                        fan,
+                        sleep: false,
+                        off_timer: None,
+                        on_timer: None,
+                    };
+
+                    let pulses = protocol.encode(&state).unwrap();
+                    let decoded = protocol.decode(&pulses).unwrap();
+                    assert_eq!(decoded.mode, mode, "{}", protocol.name());
+                    assert_eq!(decoded.fan, fan, "{}", protocol.name());
+                    assert_eq!(
+                        decoded.temperature,
+                        state.temperature,
+                        "{}",
+                        protocol.name()
+                    );
+                }
+            }
+        }
+    }
+}
@@ -1,4 +1,5 @@
 /** An IR PWM encoder/decoder with configurable pulse length */
+use serde::{Deserialize, Serialize};
 use std::{collections::HashMap, hash::Hash, time::Duration};
 use thiserror::Error;
 
@@ -18,12 +19,42 @@ impl Rule {
     }
 
     fn matches(&self, duration: Duration) -> bool {
-        let diff = self
-            .duration
+        self.diff(duration) <= self.tolerance
+    }
+
+    fn diff(&self, duration: Duration) -> Duration {
+        self.duration
             .checked_sub(duration)
-            .unwrap_or_else(|| duration.checked_sub(self.duration).unwrap());
-        diff <= self.tolerance
+            .unwrap_or_else(|| duration.checked_sub(self.duration).unwrap())
     }
+
+    /// How close a duration is to the nominal duration, from 1.0 (exact) to 0.0 (at the edge of
+    /// the tolerance, or outside of it)
+    fn score(&self, duration: Duration) -> f32 {
+        if self.tolerance.is_zero() {
+            return if self.diff(duration).is_zero() {
+                1.0
+            } else {
+                0.0
+            };
+        }
+
+        (1.0 - self.diff(duration).as_secs_f32() / self.tolerance.as_secs_f32()).max(0.0)
+    }
+}
+
+/// Serializable form of a rule, in microseconds
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RuleConfig<T> {
+    pub pulse: T,
+    pub duration_us: u64,
+    pub tolerance_us: u64,
+}
+
+/// Serializable form of a codec's rule set, as produced by calibration
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CodecConfig<T> {
+    pub rules: Vec<RuleConfig<T>>,
 }
 
 #[derive(Error, Debug, Copy, Clone)]
@@ -73,6 +104,7 @@ impl<T: Copy + Eq + Hash + std::fmt::Debug> Codec<T> {
         Ok(ret)
     }
 
+    #[cfg(test)]
     pub(crate) fn chunk_pulses(pulses: impl Iterator<Item = T>) -> Vec<(T, T)> {
         let mut pending: Option<T> = None;
         pulses
@@ -94,6 +126,38 @@ impl<T: Copy + Eq + Hash + std::fmt::Debug> Codec<T> {
             .ok_or(CodecError::InvalidPulseLength(pulse))
     }
 
+    /// Closest pulse type to a duration by ratio, even when it's outside of every rule's tolerance
+    pub fn nearest_pulse(&self, pulse: Duration) -> Option<T> {
+        let ratio = |r: &Rule| {
+            let (a, b) = (pulse.as_secs_f64(), r.duration.as_secs_f64());
+            a.max(b) / a.min(b)
+        };
+
+        self.sorted_rules
+            .iter()
+            .min_by(|a, b| ratio(&a.1).total_cmp(&ratio(&b.1)))
+            .map(|(p, _)| *p)
+    }
+
+    /// Average timing score of the given pulses against the rule they match, from 0.0 to 1.0.
+    /// Pulses that don't match any rule score 0.
+    pub fn confidence(&self, pulses: impl Iterator<Item = Duration>) -> f32 {
+        let (count, total) = pulses.fold((0usize, 0.0f32), |(count, total), pulse| {
+            let score = self
+                .sorted_rules
+                .iter()
+                .find(|(_, r)| r.matches(pulse))
+                .map_or(0.0, |(_, r)| r.score(pulse));
+            (count + 1, total + score)
+        });
+
+        if count == 0 {
+            0.0
+        } else {
+            total / count as f32
+        }
+    }
+
     pub fn encode(&self, pulses: impl Iterator<Item = T>) -> Result<Vec<Duration>, CodecError<T>> {
         let mut ret = Vec::new();
 
@@ -107,13 +171,154 @@ impl<T: Copy + Eq + Hash + std::fmt::Debug> Codec<T> {
     pub fn encode_pulse(&self, pulse: T) -> Option<Duration> {
         self.rules.get(&pulse).map(|r| r.duration)
     }
+
+    pub fn config(&self) -> CodecConfig<T> {
+        CodecConfig {
+            rules: self
+                .sorted_rules
+                .iter()
+                .map(|(pulse, rule)| RuleConfig {
+                    pulse: *pulse,
+                    duration_us: rule.duration.as_micros() as _,
+                    tolerance_us: rule.tolerance.as_micros() as _,
+                })
+                .collect(),
+        }
+    }
+
+    pub fn from_config(config: &CodecConfig<T>) -> Self {
+        Self::new(config.rules.iter().map(|r| {
+            (
+                r.pulse,
+                Rule {
+                    duration: Duration::from_micros(r.duration_us),
+                    tolerance: Duration::from_micros(r.tolerance_us),
+                },
+            )
+        }))
+    }
+
+    /// Fits the rules to captured pulses. Pulse durations are clustered, and each cluster is
+    /// assigned to the rule with the closest nominal duration. The rule's duration becomes the
+    /// cluster's mean, and its tolerance is sized to cover the cluster's spread without overlapping
+    /// neighbouring rules. Rules without any matching cluster are kept as they are.
+    pub fn calibrate(&self, pulses: impl Iterator<Item = Duration>) -> Self {
+        let mut fitted: HashMap<T, &Cluster> = HashMap::new();
+        let clusters = Cluster::find(pulses);
+
+        for cluster in &clusters {
+            let closest = self
+                .sorted_rules
+                .iter()
+                .map(|(p, r)| (*p, cluster.ratio(r.duration)))
+                .filter(|(_, ratio)| *ratio <= CALIBRATION_MAX_RATIO)
+                .min_by(|a, b| a.1.total_cmp(&b.1));
+
+            if let Some((pulse, _)) = closest {
+                // When several clusters are close to the same rule, keep the most populated one
+                let entry = fitted.entry(pulse).or_insert(cluster);
+                if cluster.samples.len() > entry.samples.len() {
+                    *entry = cluster;
+                }
+            }
+        }
+
+        let mut rules: Vec<(T, Rule)> = self
+            .sorted_rules
+            .iter()
+            .map(|&(pulse, rule)| match fitted.get(&pulse) {
+                Some(cluster) => (pulse, cluster.rule()),
+                None => (pulse, rule),
+            })
+            .collect();
+        rules.sort_by_key(|(_, r)| r.duration);
+
+        // Shrink tolerances so that neighbouring rules never overlap
+        let durations: Vec<_> = rules.iter().map(|(_, r)| r.duration).collect();
+        for (i, (_, rule)) in rules.iter_mut().enumerate() {
+            let neighbours = [
+                i.checked_sub(1).map(|j| durations[j]),
+                durations.get(i + 1).copied(),
+            ];
+            for neighbour in neighbours.into_iter().flatten() {
+                let half_gap = rule.diff(neighbour) / 2;
+                rule.tolerance = rule
+                    .tolerance
+                    .min(half_gap.saturating_sub(Duration::from_micros(1)));
+            }
+        }
+
+        Self::new(rules.into_iter())
+    }
+}
+
+/// Consecutive sorted durations further apart than this ratio start a new cluster
+const CLUSTER_SPLIT_RATIO: f64 = 1.1;
+
+/// Clusters further than this ratio from every rule's nominal duration are left out
+const CALIBRATION_MAX_RATIO: f64 = 1.5;
+
+/// A group of similar pulse durations
+#[derive(Debug)]
+struct Cluster {
+    samples: Vec<Duration>,
+}
+
+impl Cluster {
+    fn find(pulses: impl Iterator<Item = Duration>) -> Vec<Cluster> {
+        let mut pulses: Vec<_> = pulses.collect();
+        pulses.sort();
+
+        let mut clusters: Vec<Cluster> = Vec::new();
+        for pulse in pulses {
+            match clusters.last_mut() {
+                Some(cluster)
+                    if pulse.as_secs_f64()
+                        <= cluster.samples.last().unwrap().as_secs_f64() * CLUSTER_SPLIT_RATIO =>
+                {
+                    cluster.samples.push(pulse)
+                }
+                _ => clusters.push(Cluster {
+                    samples: vec![pulse],
+                }),
+            }
+        }
+
+        clusters
+    }
+
+    fn mean(&self) -> Duration {
+        self.samples.iter().sum::<Duration>() / self.samples.len() as u32
+    }
+
+    /// Ratio between the cluster's mean and the given duration, always >= 1
+    fn ratio(&self, duration: Duration) -> f64 {
+        let (mean, duration) = (self.mean().as_secs_f64(), duration.as_secs_f64());
+        mean.max(duration) / mean.min(duration)
+    }
+
+    /// Rule centered on the cluster, with a tolerance of 1.5x its largest deviation, and at least
+    /// 5% of its duration
+    fn rule(&self) -> Rule {
+        let mean = self.mean();
+        let rule = Rule {
+            duration: Duration::from_micros(mean.as_micros() as _),
+            tolerance: Duration::ZERO,
+        };
+
+        let max_diff = self.samples.iter().map(|&s| rule.diff(s)).max().unwrap();
+        Rule {
+            tolerance: (max_diff * 3 / 2).max(rule.duration / 20),
+            ..rule
+        }
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
-    #[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
+    #[derive(Debug, Clone, Copy, Eq, PartialEq, Hash, Serialize, Deserialize)]
     enum Pulse {
         Short,
         Long,
@@ -129,7 +334,7 @@ mod tests {
 
     #[test]
     fn test_decode() {
-        let pulses = [100, 500, 100, 500, 500, 500, 500, 100].map(|d| Duration::from_micros(d));
+        let pulses = [100, 500, 100, 500, 500, 500, 500, 100].map(Duration::from_micros);
         let decoded = get_codec().decode(pulses.into_iter()).unwrap();
         assert_eq!(
             decoded,
@@ -142,6 +347,76 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_nearest_pulse() {
+        let codec = get_codec();
+        let cases = [
+            (60, Pulse::Short),
+            (200, Pulse::Short),
+            (250, Pulse::Long),
+            (5000, Pulse::Long),
+        ];
+        for (micros, pulse) in cases {
+            let nearest = codec.nearest_pulse(Duration::from_micros(micros));
+            assert_eq!(nearest, Some(pulse));
+        }
+    }
+
+    #[test]
+    fn test_confidence() {
+        let codec = get_codec();
+        let exact = [100, 500].map(Duration::from_micros);
+        assert_eq!(codec.confidence(exact.into_iter()), 1.0);
+
+        // 10us off on a 20us tolerance
+        let close = [110, 500].map(Duration::from_micros);
+        assert!((codec.confidence(close.into_iter()) - 0.75).abs() < 1e-6);
+
+        let unknown = [300, 300].map(Duration::from_micros);
+        assert_eq!(codec.confidence(unknown.into_iter()), 0.0);
+        assert_eq!(codec.confidence(std::iter::empty()), 0.0);
+    }
+
+    #[test]
+    fn test_calibrate() {
+        // Pulses running long, with the long ones outside of the default tolerance
+        let pulses = [110, 620, 120, 630, 115, 610, 625, 105].map(Duration::from_micros);
+        let codec = get_codec();
+        assert!(codec.decode(pulses.into_iter()).is_err());
+
+        let calibrated = codec.calibrate(pulses.into_iter());
+        assert_eq!(
+            calibrated.encode_pulse(Pulse::Short),
+            Some(Duration::from_micros(112))
+        );
+        assert_eq!(
+            calibrated.encode_pulse(Pulse::Long),
+            Some(Duration::from_micros(621))
+        );
+        assert!(calibrated.decode(pulses.into_iter()).is_ok());
+
+        // Save and load
+        let config = calibrated.config();
+        let json = serde_json::to_string(&config).unwrap();
+        let loaded = Codec::from_config(&serde_json::from_str(&json).unwrap());
+        assert_eq!(loaded.config(), config);
+    }
+
+    #[test]
+    fn test_calibrate_no_overlap() {
+        // A wide cluster close to its neighbour mustn't make the rules overlap
+        let pulses = [90, 95, 100, 105, 110, 115, 120, 125, 140, 140].map(Duration::from_micros);
+        let rules = [
+            (Pulse::Short, Rule::new(Duration::from_micros(100))),
+            (Pulse::Long, Rule::new(Duration::from_micros(150))),
+        ];
+        let calibrated = Codec::new(rules.into_iter()).calibrate(pulses.into_iter());
+
+        let config = calibrated.config();
+        let (short, long) = (config.rules[0], config.rules[1]);
+        assert!(short.duration_us + short.tolerance_us < long.duration_us - long.tolerance_us);
+    }
+
     #[test]
     fn test_encode() {
         let pulses = [
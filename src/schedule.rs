@@ -0,0 +1,554 @@
+/** Weekly schedule of heat pump settings
+ * A JSON configuration lists programs, each applying settings to a unit at a time of day on some
+ * days of the week, and holidays replacing the weekly programs of some units on a range of dates.
+ * Settings are merged into the stored state of the unit, as with the update command, and sent
+ * when due, with retries when the transmission fails.
+ *
+ * {
+ *   "programs": [
+ *     {"unit": "bedroom", "days": ["weekdays"], "time": "06:30",
+ *      "state": {"power": true, "mode": "Heat", "temperature": 21}},
+ *     {"unit": "bedroom", "days": ["Mon", "Fri"], "time": "08:00", "state": {"power": false}}
+ *   ],
+ *   "holidays": [
+ *     {"name": "Christmas", "from": "2026-12-24", "to": "2027-01-02", "units": ["bedroom"],
+ *      "programs": [{"unit": "bedroom", "time": "09:00", "state": {"power": true}}]}
+ *   ]
+ * }
+ */
+use std::{collections::HashMap, path::PathBuf, thread, time::Duration};
+
+use anyhow::{anyhow, bail};
+use chrono::{
+    DateTime, Datelike, Local, LocalResult, NaiveDate, NaiveDateTime, NaiveTime, Offset, TimeZone,
+    Weekday,
+};
+use clap::Parser;
+use serde::{Deserialize, Deserializer};
+
+use crate::{
+    protocol::{self, Protocol},
+    state::{ControlState, StateUpdate},
+    store::Store,
+    transmitter::Transmitter,
+};
+
+#[derive(Clone, Parser, Debug)]
+pub struct ScheduleOpts {
+    /// JSON file holding the weekly programs and holidays
+    pub config: PathBuf,
+
+    /// Print the transmissions of the coming days, and the states they result in, without
+    /// sending anything
+    #[clap(long)]
+    pub dry_run: bool,
+
+    /// Number of days printed by --dry-run
+    #[clap(long, default_value = "7")]
+    pub days: u32,
+
+    /// Attempts made after a failed transmission
+    #[clap(long, default_value = "3")]
+    pub retries: u32,
+
+    /// Seconds to wait between attempts
+    #[clap(long, default_value = "30")]
+    pub retry_delay: u64,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    #[serde(default)]
+    pub programs: Vec<Program>,
+    #[serde(default)]
+    pub holidays: Vec<Holiday>,
+}
+
+/// Settings applied to a unit at a time of day
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Program {
+    pub unit: String,
+    /// Days of the week (e.g. Mon or monday), weekdays, weekend or daily. Every day when missing.
+    #[serde(default = "every_day", deserialize_with = "deserialize_days")]
+    pub days: Vec<Weekday>,
+    /// Local time, e.g. 06:30
+    #[serde(deserialize_with = "deserialize_time")]
+    pub time: NaiveTime,
+    /// Complete state, or only the settings to change
+    pub state: StateUpdate,
+}
+
+/// Dates on which the weekly programs of some units are replaced by the holiday's programs
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Holiday {
+    #[serde(default)]
+    pub name: String,
+    /// First day of the holiday, e.g. 2026-12-24
+    #[serde(deserialize_with = "deserialize_date")]
+    pub from: NaiveDate,
+    /// Last day of the holiday, included
+    #[serde(deserialize_with = "deserialize_date")]
+    pub to: NaiveDate,
+    /// Units whose weekly programs are suspended, every unit when empty
+    #[serde(default)]
+    pub units: Vec<String>,
+    /// Programs run instead, none leaves the units as they are
+    #[serde(default)]
+    pub programs: Vec<Program>,
+}
+
+impl Holiday {
+    fn contains(&self, date: NaiveDate) -> bool {
+        (self.from..=self.to).contains(&date)
+    }
+
+    fn suspends(&self, unit: &str) -> bool {
+        self.units.is_empty() || self.units.iter().any(|u| u == unit)
+    }
+}
+
+/// Settings due to be sent to a unit
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Transmission<Tz: TimeZone = Local> {
+    pub at: DateTime<Tz>,
+    pub unit: String,
+    pub state: StateUpdate,
+    /// Name of the holiday the program belongs to
+    pub holiday: Option<String>,
+}
+
+fn every_day() -> Vec<Weekday> {
+    use Weekday::*;
+    vec![Mon, Tue, Wed, Thu, Fri, Sat, Sun]
+}
+
+fn deserialize_days<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<Weekday>, D::Error> {
+    let mut days = Vec::new();
+    for name in Vec::<String>::deserialize(deserializer)? {
+        match name.to_lowercase().as_str() {
+            "weekdays" => days.extend(&every_day()[..5]),
+            "weekend" => days.extend([Weekday::Sat, Weekday::Sun]),
+            "daily" => days.extend(every_day()),
+            _ => days.push(
+                name.parse()
+                    .map_err(|_| serde::de::Error::custom(format!("invalid day: {}", name)))?,
+            ),
+        }
+    }
+    Ok(days)
+}
+
+fn deserialize_time<'de, D: Deserializer<'de>>(deserializer: D) -> Result<NaiveTime, D::Error> {
+    let time = String::deserialize(deserializer)?;
+    NaiveTime::parse_from_str(&time, "%H:%M")
+        .or_else(|_| NaiveTime::parse_from_str(&time, "%H:%M:%S"))
+        .map_err(|_| {
+            serde::de::Error::custom(format!("invalid time: {} (expected e.g. 06:30)", time))
+        })
+}
+
+/// Instant a local date and time falls on. Times repeated when the clocks go back are the first
+/// occurrence, times skipped when they go forward are read with the offset in effect before.
+fn instant<Tz: TimeZone>(zone: &Tz, time: NaiveDateTime) -> Option<DateTime<Tz>> {
+    match zone.from_local_datetime(&time) {
+        LocalResult::Single(at) | LocalResult::Ambiguous(at, _) => Some(at),
+        LocalResult::None => {
+            let before = zone
+                .offset_from_local_datetime(&(time - chrono::Duration::days(1)))
+                .earliest()?;
+            Some(zone.from_utc_datetime(&(time - before.fix())))
+        }
+    }
+}
+
+fn deserialize_date<'de, D: Deserializer<'de>>(deserializer: D) -> Result<NaiveDate, D::Error> {
+    let date = String::deserialize(deserializer)?;
+    NaiveDate::parse_from_str(&date, "%Y-%m-%d").map_err(|_| {
+        serde::de::Error::custom(format!("invalid date: {} (expected e.g. 2026-12-24)", date))
+    })
+}
+
+impl Config {
+    /// Checks that every program's settings can be encoded, on top of the initial state
+    pub fn validate(&self, protocol: &dyn Protocol) -> anyhow::Result<()> {
+        let holiday_programs = self.holidays.iter().flat_map(|h| &h.programs);
+        for program in self.programs.iter().chain(holiday_programs) {
+            let state = program.state.apply(protocol::initial_state(protocol));
+            protocol
+                .encode(&state)
+                .map_err(|e| anyhow!("{} at {}: {:#}", program.unit, program.time, e))?;
+        }
+
+        for holiday in &self.holidays {
+            if holiday.from > holiday.to {
+                bail!("holiday {} ends before it starts", holiday.name);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Transmissions due after `after`, up to `until` included, in order. Program times are local
+    /// to the time zone of `after`.
+    pub fn transmissions<Tz: TimeZone>(
+        &self,
+        after: &DateTime<Tz>,
+        until: &DateTime<Tz>,
+    ) -> Vec<Transmission<Tz>> {
+        let zone = after.timezone();
+        let mut transmissions = Vec::new();
+
+        for date in after
+            .date_naive()
+            .iter_days()
+            .take_while(|&date| date <= until.date_naive())
+        {
+            let holidays: Vec<_> = self.holidays.iter().filter(|h| h.contains(date)).collect();
+
+            let weekly = self
+                .programs
+                .iter()
+                .filter(|program| !holidays.iter().any(|h| h.suspends(&program.unit)))
+                .map(|program| (program, None));
+            let holiday = holidays.iter().flat_map(|holiday| {
+                holiday
+                    .programs
+                    .iter()
+                    .map(|program| (program, Some(holiday.name.clone())))
+            });
+
+            for (program, holiday) in weekly.chain(holiday) {
+                if !program.days.contains(&date.weekday()) {
+                    continue;
+                }
+                match instant(&zone, date.and_time(program.time)) {
+                    Some(at) if after < &at && &at <= until => transmissions.push(Transmission {
+                        at,
+                        unit: program.unit.clone(),
+                        state: program.state,
+                        holiday,
+                    }),
+                    _ => {}
+                }
+            }
+        }
+
+        // Programs due at the same time are kept in the order of the configuration
+        transmissions.sort_by(|a, b| a.at.cmp(&b.at));
+        transmissions
+    }
+}
+
+/// States the transmissions would result in, starting from the stored states
+pub fn simulate<Tz: TimeZone>(
+    protocol: &dyn Protocol,
+    store: &Store,
+    transmissions: &[Transmission<Tz>],
+) -> anyhow::Result<Vec<ControlState>> {
+    let mut states = HashMap::new();
+    let initial = protocol::initial_state(protocol);
+
+    transmissions
+        .iter()
+        .map(|transmission| {
+            let state = match states.get(&transmission.unit) {
+                Some(&state) => state,
+                None => store.state(&transmission.unit, initial)?,
+            };
+            let state = transmission.state.apply(state);
+            states.insert(transmission.unit.clone(), state);
+            Ok(state)
+        })
+        .collect()
+}
+
+/// Merges a transmission into the stored state of its unit and sends it, trying again after
+/// `delay` up to `retries` times
+pub fn deliver<Tz: TimeZone>(
+    protocol: &dyn Protocol,
+    store: &Store,
+    transmitter: &mut dyn Transmitter,
+    transmission: &Transmission<Tz>,
+    retries: u32,
+    delay: Duration,
+) -> anyhow::Result<ControlState> {
+    let initial = protocol::initial_state(protocol);
+    let mut attempt = 0;
+
+    loop {
+        let result = store.update(&transmission.unit, initial, |state| {
+            let state = transmission.state.apply(state);
+            transmitter.transmit(protocol.carrier_frequency(), protocol.encode(&state)?)?;
+            anyhow::Ok(state)
+        });
+
+        match result {
+            Err(e) if attempt < retries => {
+                attempt += 1;
+                eprintln!(
+                    "{}: Error: {:#}, retrying in {}s ({}/{})",
+                    transmission.unit,
+                    e,
+                    delay.as_secs(),
+                    attempt,
+                    retries
+                );
+                thread::sleep(delay);
+            }
+            result => return result,
+        }
+    }
+}
+
+fn describe(transmission: &Transmission, state: &ControlState) -> String {
+    let mut description = format!(
+        "{} {}: {:?}",
+        transmission.at.format("%a %Y-%m-%d %H:%M"),
+        transmission.unit,
+        state
+    );
+    if let Some(holiday) = &transmission.holiday {
+        description += &format!(" (holiday: {})", holiday);
+    }
+    description
+}
+
+pub fn load(protocol: &dyn Protocol, opts: &ScheduleOpts) -> anyhow::Result<Config> {
+    let config: Config = serde_json::from_reader(std::fs::File::open(&opts.config)?)?;
+    config.validate(protocol)?;
+    Ok(config)
+}
+
+/// Prints the transmissions of the coming days
+pub fn dry_run(protocol: &dyn Protocol, store: &Store, opts: &ScheduleOpts) -> anyhow::Result<()> {
+    let config = load(protocol, opts)?;
+
+    let now = Local::now();
+    let until = now + chrono::Duration::days(opts.days.into());
+    let transmissions = config.transmissions(&now, &until);
+
+    for (transmission, state) in
+        transmissions
+            .iter()
+            .zip(simulate(protocol, store, &transmissions)?)
+    {
+        println!("{}", describe(transmission, &state));
+    }
+
+    Ok(())
+}
+
+/// Sends the transmissions as they're due, until interrupted. Progress is printed to stderr,
+/// leaving stdout to the codes printed by --print.
+pub fn run(
+    protocol: &dyn Protocol,
+    store: &Store,
+    transmitter: &mut dyn Transmitter,
+    opts: &ScheduleOpts,
+) -> anyhow::Result<()> {
+    // Sleeps are short enough to follow clock changes
+    const MAX_SLEEP: Duration = Duration::from_secs(60);
+
+    let config = load(protocol, opts)?;
+    let delay = Duration::from_secs(opts.retry_delay);
+    eprintln!(
+        "Running {} programs and {} holidays",
+        config.programs.len(),
+        config.holidays.len()
+    );
+
+    // Instants rather than local times, which repeat when the clocks go back
+    let mut last = Local::now();
+    loop {
+        let now = Local::now();
+        for transmission in config.transmissions(&last, &now) {
+            match deliver(
+                protocol,
+                store,
+                transmitter,
+                &transmission,
+                opts.retries,
+                delay,
+            ) {
+                Ok(state) => eprintln!("{}", describe(&transmission, &state)),
+                Err(e) => eprintln!("{} {}: Error: {:#}", transmission.at, transmission.unit, e),
+            }
+        }
+        last = last.max(now);
+
+        let next = config
+            .transmissions(&now, &(now + chrono::Duration::days(1)))
+            .first()
+            .and_then(|transmission| (transmission.at - now).to_std().ok())
+            .unwrap_or(MAX_SLEEP);
+        thread::sleep(next.min(MAX_SLEEP));
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use chrono::Utc;
+    use chrono_tz::Europe::Paris;
+
+    use super::*;
+    use crate::{
+        lennox::Lennox,
+        state::{Fan, Mode, Temperature},
+        store::TempStore,
+    };
+
+    fn config() -> Config {
+        serde_json::from_value(serde_json::json!({
+            "programs": [
+                {"unit": "bedroom", "days": ["weekdays"], "time": "06:30",
+                 "state": {"power": true, "mode": "Heat", "temperature": 21}},
+                {"unit": "bedroom", "days": ["weekdays"], "time": "08:00", "state": {"power": false}},
+                {"unit": "living", "days": ["Saturday", "sun"], "time": "09:00",
+                 "state": {"power": true, "mode": "Cool", "temperature": 24, "fan": "Auto"}},
+            ],
+            "holidays": [
+                {"name": "Christmas", "from": "2026-12-24", "to": "2026-12-26", "units": ["bedroom"],
+                 "programs": [{"unit": "bedroom", "time": "09:00", "state": {"power": true}}]},
+            ],
+        }))
+        .unwrap()
+    }
+
+    fn at(date: &str) -> DateTime<Utc> {
+        NaiveDateTime::parse_from_str(date, "%Y-%m-%d %H:%M")
+            .unwrap()
+            .and_utc()
+    }
+
+    #[test]
+    fn test_transmissions() {
+        let config = config();
+        config.validate(&Lennox::new()).unwrap();
+
+        // Friday afternoon to Monday morning
+        let transmissions = config.transmissions(&at("2026-10-16 12:00"), &at("2026-10-19 06:30"));
+        let times: Vec<_> = transmissions
+            .iter()
+            .map(|t| (t.at.format("%a %H:%M").to_string(), t.unit.as_str()))
+            .collect();
+        assert_eq!(
+            times,
+            [
+                ("Sat 09:00".into(), "living"),
+                ("Sun 09:00".into(), "living"),
+                ("Mon 06:30".into(), "bedroom"),
+            ]
+        );
+
+        // Christmas replaces the bedroom's weekly programs, the living room's still run
+        let transmissions = config.transmissions(&at("2026-12-24 00:00"), &at("2026-12-27 23:59"));
+        let bedroom: Vec<_> = transmissions
+            .iter()
+            .filter(|t| t.unit == "bedroom")
+            .collect();
+        assert_eq!(bedroom.len(), 3);
+        assert!(bedroom
+            .iter()
+            .all(|t| t.holiday.as_deref() == Some("Christmas")));
+        assert_eq!(
+            transmissions.iter().filter(|t| t.unit == "living").count(),
+            2
+        );
+    }
+
+    #[test]
+    fn test_clock_changes() {
+        let config: Config = serde_json::from_value(serde_json::json!({"programs": [
+            {"unit": "bedroom", "time": "02:30", "state": {"power": true}},
+        ]}))
+        .unwrap();
+        let paris = |date: &str| at(date).with_timezone(&Paris);
+
+        // Clocks go back from 03:00 to 02:00: 02:30 happens twice, the program runs once, checked
+        // every ten minutes as run does
+        let mut last = paris("2026-10-24 23:00");
+        let mut times = Vec::new();
+        while last < paris("2026-10-25 03:00") {
+            let now = last + chrono::Duration::minutes(10);
+            times.extend(config.transmissions(&last, &now).into_iter().map(|t| t.at));
+            last = now;
+        }
+        assert_eq!(times, [paris("2026-10-25 00:30")]);
+
+        // Clocks go forward from 02:00 to 03:00: 02:30 doesn't happen, the program runs at 03:30
+        let transmissions =
+            config.transmissions(&paris("2026-03-28 12:00"), &paris("2026-03-29 12:00"));
+        assert_eq!(transmissions.len(), 1);
+        assert_eq!(transmissions[0].at, paris("2026-03-29 01:30"));
+        assert_eq!(transmissions[0].at.format("%H:%M").to_string(), "03:30");
+    }
+
+    #[test]
+    fn test_invalid_config() {
+        let hot = serde_json::json!({"programs": [
+            {"unit": "bedroom", "time": "06:30", "state": {"temperature": 35}},
+        ]});
+        let config: Config = serde_json::from_value(hot).unwrap();
+        assert!(config.validate(&Lennox::new()).is_err());
+
+        let day = serde_json::json!({"programs": [
+            {"unit": "bedroom", "days": ["someday"], "time": "06:30", "state": {}},
+        ]});
+        assert!(serde_json::from_value::<Config>(day).is_err());
+
+        let time = serde_json::json!({"programs": [
+            {"unit": "bedroom", "time": "6h30", "state": {}},
+        ]});
+        assert!(serde_json::from_value::<Config>(time).is_err());
+    }
+
+    #[test]
+    fn test_simulate_deliver() {
+        let lennox = Lennox::new();
+        let store = TempStore::new("schedule");
+
+        let transmissions = config().transmissions(&at("2026-10-19 00:00"), &at("2026-10-19 23:59"));
+        let states = simulate(&lennox, &store, &transmissions).unwrap();
+        assert_eq!(states[0].temperature, Some(Temperature::Celsius(21)));
+        assert!(!states[1].power);
+        // The second program only turns the unit off, keeping the other settings
+        assert_eq!(states[1].mode, Mode::Heat);
+
+        // The first attempt fails, the retry goes through
+        let mut attempts = 0;
+        let mut transmitter = |_, pulses: Vec<Duration>| {
+            attempts += 1;
+            match attempts {
+                1 => Err(anyhow!("device unreachable")),
+                _ => Ok(lennox.decode(&pulses).map(|_| ())?),
+            }
+        };
+        let state = deliver(
+            &lennox,
+            &store,
+            &mut transmitter,
+            &transmissions[0],
+            1,
+            Duration::ZERO,
+        )
+        .unwrap();
+        assert_eq!(state, states[0]);
+        assert_eq!(attempts, 2);
+        assert_eq!(store.state("bedroom", state).unwrap().fan, Fan::Auto);
+
+        let mut transmitter = |_, _| Err(anyhow!("device unreachable"));
+        let result = deliver(
+            &lennox,
+            &store,
+            &mut transmitter,
+            &transmissions[1],
+            2,
+            Duration::ZERO,
+        );
+        assert!(result.is_err());
+        assert!(store.state("bedroom", states[1]).unwrap().power);
+    }
+}
@@ -0,0 +1,433 @@
+/** HTTP/JSON API exposing the encoder to tools that would otherwise shell out to the CLI
+ * POST /encode: ControlState -> code in broadlink hex, base64 and Pronto, and raw pulses
+ * POST /decode: {"code": "...", "format": "pronto"} -> ControlState, the format defaults to
+ * broadlink (hex or base64)
+ * GET /states: SmartIR code file holding every state
+ * POST /send: ControlState -> transmitted through the configured backend
+ * GET /state/<unit>: ControlState assumed for the unit, from the state store
+ * POST /state/<unit>: StateUpdate -> merged into the stored state, then transmitted through the
+ * configured backend, returning the new ControlState
+ * Failed requests return {"error": kind, "message": "..."} with a matching status code.
+ */
+use std::net::SocketAddr;
+
+use anyhow::anyhow;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use thiserror::Error;
+use tiny_http::{Header, Method, Request, Response, Server};
+
+use crate::{
+    broadlink::Recording,
+    protocol::{self, Protocol},
+    smartir,
+    state::{ControlState, StateUpdate},
+    store::{Store, StoreError},
+    transmitter::Transmitter,
+    Format,
+};
+
+#[derive(Error, Debug)]
+pub enum ApiError {
+    #[error("no such endpoint: {0}")]
+    NotFound(String),
+    #[error("method not allowed: {0}")]
+    MethodNotAllowed(String),
+    #[error("invalid request: {0}")]
+    InvalidRequest(String),
+    #[error("invalid code: {0}")]
+    InvalidCode(String),
+    #[error("decode failed: {0}")]
+    Decode(String),
+    #[error("encode failed: {0}")]
+    Encode(String),
+    #[error("transmit failed: {0}")]
+    Transmit(String),
+    #[error(transparent)]
+    Store(#[from] StoreError),
+}
+
+impl ApiError {
+    pub fn status(&self) -> u16 {
+        match self {
+            ApiError::NotFound(_) => 404,
+            ApiError::MethodNotAllowed(_) => 405,
+            ApiError::InvalidRequest(_) | ApiError::InvalidCode(_) => 400,
+            ApiError::Decode(_) | ApiError::Encode(_) => 422,
+            ApiError::Transmit(_) => 502,
+            ApiError::Store(_) => 500,
+        }
+    }
+
+    /// Machine readable kind of error
+    pub fn kind(&self) -> &'static str {
+        match self {
+            ApiError::NotFound(_) => "not_found",
+            ApiError::MethodNotAllowed(_) => "method_not_allowed",
+            ApiError::InvalidRequest(_) => "invalid_request",
+            ApiError::InvalidCode(_) => "invalid_code",
+            ApiError::Decode(_) => "decode",
+            ApiError::Encode(_) => "encode",
+            ApiError::Transmit(_) => "transmit",
+            ApiError::Store(_) => "store",
+        }
+    }
+
+    pub fn to_json(&self) -> Value {
+        json!({ "error": self.kind(), "message": self.to_string() })
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct DecodeRequest {
+    code: String,
+    #[serde(default)]
+    format: Option<String>,
+}
+
+pub struct Api<'a> {
+    protocol: &'a dyn Protocol,
+    store: &'a Store,
+    transmitter: &'a mut dyn Transmitter,
+}
+
+impl<'a> Api<'a> {
+    pub fn new(
+        protocol: &'a dyn Protocol,
+        store: &'a Store,
+        transmitter: &'a mut dyn Transmitter,
+    ) -> Self {
+        Self {
+            protocol,
+            store,
+            transmitter,
+        }
+    }
+
+    /// Handles a request, returning the JSON response
+    pub fn handle(&mut self, method: &Method, url: &str, body: &[u8]) -> Result<Value, ApiError> {
+        let path = url.split('?').next().unwrap_or_default();
+
+        match (method, path) {
+            (Method::Post, "/encode") => self.encode(&parse(body)?),
+            (Method::Post, "/decode") => self.decode(&parse(body)?),
+            (Method::Get, "/states") => smartir::gen_code_file(self.protocol)
+                .and_then(|code_file| Ok(serde_json::to_value(code_file)?))
+                .map_err(|e| ApiError::Encode(format!("{:#}", e))),
+            (Method::Post, "/send") => self.send(&parse(body)?),
+            (_, "/encode" | "/decode" | "/states" | "/send") => {
+                Err(ApiError::MethodNotAllowed(method.to_string()))
+            }
+            _ => match path.strip_prefix("/state/").filter(|unit| !unit.is_empty()) {
+                Some(unit) if method == &Method::Get => {
+                    let state = self
+                        .store
+                        .state(unit, protocol::initial_state(self.protocol))?;
+                    Ok(serde_json::to_value(state).unwrap())
+                }
+                Some(unit) if method == &Method::Post => self.update(unit, &parse(body)?),
+                Some(_) => Err(ApiError::MethodNotAllowed(method.to_string())),
+                None => Err(ApiError::NotFound(path.into())),
+            },
+        }
+    }
+
+    fn encode(&self, state: &ControlState) -> Result<Value, ApiError> {
+        let pulses = self
+            .protocol
+            .encode(state)
+            .map_err(|e| ApiError::Encode(format!("{:#}", e)))?;
+        let frequency = self.protocol.carrier_frequency();
+        let recording = Recording::new_ir(pulses.clone());
+
+        Ok(json!({
+            "broadlink": hex::encode(recording.to_bytes()),
+            "base64": base64::encode(recording.to_bytes()),
+            "pronto": Format::Pronto.format(frequency, pulses.clone()),
+            "pulses": pulses.iter().map(|p| p.as_micros() as u64).collect::<Vec<_>>(),
+        }))
+    }
+
+    fn decode(&self, request: &DecodeRequest) -> Result<Value, ApiError> {
+        let format: Format = match &request.format {
+            Some(format) => format
+                .parse()
+                .map_err(|_| ApiError::InvalidRequest(format!("unknown format: {}", format)))?,
+            None => Format::Broadlink,
+        };
+
+        let recording = format
+            .read(request.code.as_bytes())
+            .next()
+            .unwrap_or_else(|| Err(anyhow!("empty code")))
+            .map_err(|e| ApiError::InvalidCode(format!("{:#}", e)))?;
+        if recording.transport.is_rf() {
+            return Err(ApiError::Decode(
+                "RF codes don't hold heat pump states".into(),
+            ));
+        }
+
+        let state = self
+            .protocol
+            .decode(&recording.pulses())
+            .map_err(|e| ApiError::Decode(format!("{:#}", e)))?;
+        Ok(serde_json::to_value(state).unwrap())
+    }
+
+    fn send(&mut self, state: &ControlState) -> Result<Value, ApiError> {
+        let pulses = self
+            .protocol
+            .encode(state)
+            .map_err(|e| ApiError::Encode(format!("{:#}", e)))?;
+        self.transmitter
+            .transmit(self.protocol.carrier_frequency(), pulses)
+            .map_err(|e| ApiError::Transmit(format!("{:#}", e)))?;
+
+        Ok(json!({ "sent": state }))
+    }
+
+    /// Merges an update into the stored state of a unit, only stored once sent
+    fn update(&mut self, unit: &str, update: &StateUpdate) -> Result<Value, ApiError> {
+        let initial = protocol::initial_state(self.protocol);
+        let state = self.store.update(unit, initial, |state| {
+            let state = update.apply(state);
+            self.send(&state)?;
+            Ok::<_, ApiError>(state)
+        })?;
+
+        Ok(serde_json::to_value(state).unwrap())
+    }
+}
+
+fn parse<'de, T: Deserialize<'de>>(body: &'de [u8]) -> Result<T, ApiError> {
+    serde_json::from_slice(body).map_err(|e| ApiError::InvalidRequest(e.to_string()))
+}
+
+/// Answers requests until the server is dropped
+pub fn serve(server: &Server, api: &mut Api) {
+    for request in server.incoming_requests() {
+        if let Err(e) = respond(request, api) {
+            eprintln!("Error: {:#}", e);
+        }
+    }
+}
+
+fn respond(mut request: Request, api: &mut Api) -> anyhow::Result<()> {
+    let mut body = Vec::new();
+    request.as_reader().read_to_end(&mut body)?;
+
+    let (status, response) = match api.handle(request.method(), request.url(), &body) {
+        Ok(response) => (200, response),
+        Err(e) => {
+            eprintln!("{} {}: Error: {}", request.method(), request.url(), e);
+            (e.status(), e.to_json())
+        }
+    };
+
+    let content_type = Header::from_bytes("Content-Type", "application/json").unwrap();
+    request.respond(
+        Response::from_string(response.to_string())
+            .with_status_code(status)
+            .with_header(content_type),
+    )?;
+    Ok(())
+}
+
+/// Runs the API on the given address until interrupted
+pub fn run(
+    protocol: &dyn Protocol,
+    listen: SocketAddr,
+    store: &Store,
+    transmitter: &mut dyn Transmitter,
+) -> anyhow::Result<()> {
+    let server = Server::http(listen).map_err(|e| anyhow!(e))?;
+    eprintln!("Listening on http://{}", listen);
+
+    serve(&server, &mut Api::new(protocol, store, transmitter));
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        io::{Read, Write},
+        net::TcpStream,
+        thread,
+        time::Duration,
+    };
+
+    use super::*;
+    use crate::{
+        broadlink::{client::Client, mock::MockDevice},
+        lennox::Lennox,
+        state::{Fan, Mode, Temperature},
+        store::TempStore,
+    };
+
+    fn cool_22() -> ControlState {
+        ControlState {
+            power: true,
+            mode: Mode::Cool,
+            temperature: Some(Temperature::Celsius(22)),
+            fan: Fan::Max,
+            sleep: false,
+            off_timer: None,
+            on_timer: None,
+        }
+    }
+
+    #[test]
+    fn test_encode_decode() {
+        let lennox = Lennox::new();
+        let store = TempStore::new("server-encode");
+        let mut transmitter = |_, _| Ok(());
+        let mut api = Api::new(&lennox, &store, &mut transmitter);
+
+        let body = br#"{"power": true, "mode": "Cool", "temperature": 22, "fan": "Max"}"#;
+        let encoded = api.handle(&Method::Post, "/encode", body).unwrap();
+        assert!(!encoded["pulses"].as_array().unwrap().is_empty());
+
+        for (format, code) in [
+            ("broadlink", "base64"),
+            ("broadlink", "broadlink"),
+            ("pronto", "pronto"),
+        ] {
+            let request = json!({ "code": encoded[code], "format": format });
+            let decoded = api
+                .handle(&Method::Post, "/decode", request.to_string().as_bytes())
+                .unwrap();
+            assert_eq!(
+                serde_json::from_value::<ControlState>(decoded).unwrap(),
+                cool_22()
+            );
+        }
+
+        let states = api.handle(&Method::Get, "/states?pretty", b"").unwrap();
+        assert_eq!(states["manufacturer"], "Lennox");
+    }
+
+    #[test]
+    fn test_errors() {
+        let lennox = Lennox::new();
+        let store = TempStore::new("server-errors");
+        let mut transmitter = |_, _| Err(anyhow!("device unreachable"));
+        let mut api = Api::new(&lennox, &store, &mut transmitter);
+
+        let kind = |result: Result<Value, ApiError>| result.unwrap_err().kind();
+        assert_eq!(kind(api.handle(&Method::Get, "/", b"")), "not_found");
+        assert_eq!(
+            kind(api.handle(&Method::Get, "/encode", b"")),
+            "method_not_allowed"
+        );
+        assert_eq!(
+            kind(api.handle(&Method::Post, "/encode", b"{}")),
+            "invalid_request"
+        );
+
+        let hot = br#"{"power": true, "mode": "Heat", "temperature": 40, "fan": "Auto"}"#;
+        assert_eq!(kind(api.handle(&Method::Post, "/encode", hot)), "encode");
+
+        let garbage = br#"{"code": "not a code"}"#;
+        assert_eq!(
+            kind(api.handle(&Method::Post, "/decode", garbage)),
+            "invalid_code"
+        );
+
+        // A valid recording of another remote
+        let nec = br#"{"code": "JgAcAB0dHB44HhweGx4cHR06HB0cHhwdHB8bHhwADQUAAAAAAAAAAAAAAAA="}"#;
+        assert_eq!(kind(api.handle(&Method::Post, "/decode", nec)), "decode");
+
+        let state = serde_json::to_vec(&cool_22()).unwrap();
+        let error = api.handle(&Method::Post, "/send", &state).unwrap_err();
+        assert_eq!(error.status(), 502);
+        assert_eq!(
+            error.to_json()["message"],
+            "transmit failed: device unreachable"
+        );
+
+        // Updates that weren't sent aren't stored
+        let update = api.handle(&Method::Post, "/state/bedroom", br#"{"fan": "Max"}"#);
+        assert_eq!(kind(update), "transmit");
+        assert!(store.states().unwrap().is_empty());
+        assert_eq!(
+            kind(api.handle(&Method::Post, "/state/bedroom", br#"{"speed": 3}"#)),
+            "invalid_request"
+        );
+    }
+
+    #[test]
+    fn test_state() {
+        let lennox = Lennox::new();
+        let store = TempStore::new("server-state");
+        let mut sent = Vec::new();
+        let mut transmitter = |_, pulses: Vec<Duration>| {
+            sent.push(lennox.decode(&pulses)?);
+            Ok(())
+        };
+        let mut api = Api::new(&lennox, &store, &mut transmitter);
+
+        let state = api.handle(&Method::Get, "/state/bedroom", b"").unwrap();
+        assert_eq!(state["power"], false);
+
+        // Partial updates are merged into the stored state before being sent
+        let cool = br#"{"power": true, "mode": "Cool", "temperature": 22}"#;
+        api.handle(&Method::Post, "/state/bedroom", cool).unwrap();
+        let state = api
+            .handle(&Method::Post, "/state/bedroom", br#"{"fan": "Max"}"#)
+            .unwrap();
+        assert_eq!(
+            serde_json::from_value::<ControlState>(state).unwrap(),
+            cool_22()
+        );
+        assert_eq!(
+            api.handle(&Method::Get, "/state/bedroom", b"").unwrap()["fan"],
+            "Max"
+        );
+        assert_eq!(
+            api.handle(&Method::Get, "/state/", b"").unwrap_err().kind(),
+            "not_found"
+        );
+
+        assert_eq!(sent.last(), Some(&cool_22()));
+        assert_eq!(store.states().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_send() {
+        let mock = MockDevice::spawn(0x5216).unwrap();
+        let device = crate::broadlink::client::discover(mock.addr(), Duration::from_secs(1))
+            .unwrap()
+            .remove(0);
+        let mut client = Client::connect(device, Duration::from_secs(1)).unwrap();
+
+        let server = Server::http("127.0.0.1:0").unwrap();
+        let addr = server.server_addr().to_ip().unwrap();
+        let store = TempStore::new("server-send");
+        let response = thread::scope(|scope| {
+            scope.spawn(|| serve(&server, &mut Api::new(&Lennox::new(), &store, &mut client)));
+
+            let body = serde_json::to_string(&cool_22()).unwrap();
+            let mut stream = TcpStream::connect(addr).unwrap();
+            write!(
+                stream,
+                "POST /send HTTP/1.1\r\nHost: localhost\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                body.len(),
+                body
+            )
+            .unwrap();
+            let mut response = String::new();
+            stream.read_to_string(&mut response).unwrap();
+            server.unblock();
+            response
+        });
+        assert!(response.starts_with("HTTP/1.1 200"), "{}", response);
+        assert!(response.contains("application/json"));
+
+        let received = mock.received();
+        assert_eq!(received.len(), 1);
+        assert_eq!(
+            Lennox::new().decode(&received[0].pulses()).unwrap(),
+            cool_22()
+        );
+    }
+}
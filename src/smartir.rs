@@ -1,10 +1,9 @@
 use serde::{Deserialize, Serialize};
-use serde_json::json;
-use strum::IntoEnumIterator;
 
 use crate::{
     broadlink::Recording,
-    lennox::{packet::Packet, ControlState, Fan, Mode, Phy},
+    protocol::{self, Protocol},
+    state::ControlState,
 };
 
 /*
@@ -55,107 +54,118 @@ pub struct CodeFile {
 */
 
 /// Generates a SmartIR code file from all possible states
-pub fn gen_smartir() -> anyhow::Result<()> {
-    let commands: serde_json::Value = {
-        // Commands are nested to represent all possible states, the hierarchy used in other models is:
-        // mode -> fan -> temperature
-
-        let mut all_commands = serde_json::Map::new();
-
-        for mode in Mode::iter() {
-            let mode_map = all_commands
-                .entry(mode.as_ref().to_lowercase())
-                .or_insert(serde_json::Map::new().into());
-            let mode_map = mode_map.as_object_mut().unwrap();
-
-            for fan in Fan::iter() {
-                if fan == Fan::Zero {
-                    continue;
-                }
-
-                match mode {
-                    Mode::Heat | Mode::Dry | Mode::Cool | Mode::Auto => {
-                        let fan_map = mode_map
-                            .entry(fan.as_ref().to_lowercase())
-                            .or_insert(serde_json::Map::new().into());
-                        let fan_map = fan_map.as_object_mut().unwrap();
-
-                        for temperature in 17..=30 {
-                            let state = ControlState {
-                                power: true,
-                                mode,
-                                fan,
-                                temperature: if mode == Mode::Fan {
-                                    None
-                                } else {
-                                    Some(temperature)
-                                },
-                            };
-
-                            fan_map
-                                .insert(format!("{}", temperature), encode_state(&state)?.into());
-                        }
-                    }
-                    Mode::Fan => {
-                        let state = ControlState {
-                            power: true,
-                            mode,
-                            fan,
-                            temperature: None,
-                        };
-
-                        mode_map.insert(fan.as_ref().to_lowercase(), encode_state(&state)?.into());
-                    }
-                }
+pub fn gen_smartir(protocol: &dyn Protocol) -> anyhow::Result<()> {
+    let code_file = gen_code_file(protocol)?;
+    println!("{}", serde_json::to_string_pretty(&code_file)?);
+
+    Ok(())
+}
+
+pub fn gen_code_file(protocol: &dyn Protocol) -> anyhow::Result<CodeFile> {
+    // Commands are nested to represent all possible states, the hierarchy used in other models is:
+    // mode -> fan -> temperature, followed by "off"
+    let mut commands = serde_json::Map::new();
+    let mut off_commands = serde_json::Map::new();
+    for (name, state) in protocol::named_states(protocol) {
+        // SmartIR's climate schema has no presets, so sleep is left out
+        if state.sleep {
+            continue;
+        }
+
+        let code = encode_state(protocol, &state)?.into();
+        if !state.power {
+            off_commands.insert(name, code);
+            continue;
+        }
+
+        let mode_map = commands
+            .entry(state.mode.as_ref().to_lowercase())
+            .or_insert(serde_json::Map::new().into())
+            .as_object_mut()
+            .unwrap();
+        let fan = state.fan.as_ref().to_lowercase();
+
+        match state.temperature {
+            Some(temperature) => {
+                mode_map
+                    .entry(fan)
+                    .or_insert(serde_json::Map::new().into())
+                    .as_object_mut()
+                    .unwrap()
+                    .insert(temperature.value().to_string(), code);
+            }
+            None => {
+                mode_map.insert(fan, code);
             }
         }
+    }
 
-        // Add "Off" state
-        let off_state = ControlState {
-            power: false,
-            mode: Mode::Auto,
-            fan: Fan::Auto,
-            temperature: None,
-        };
-        all_commands.insert("off".into(), encode_state(&off_state)?.into());
-
-        all_commands.into()
-    };
-
-    let code_file = CodeFile {
-        manufacturer: "Lennox".into(),
-        supported_models: vec!["MWMA018S4-2P".into(), "RG57A6/BGEFU1".into()],
+    commands.append(&mut off_commands);
+
+    let temperature_range = protocol.temperature_range();
+
+    Ok(CodeFile {
+        manufacturer: protocol.manufacturer().into(),
+        supported_models: protocol.models().iter().map(|&m| m.into()).collect(),
         supported_controller: "Broadlink".into(),
         commands_encoding: "Base64".into(),
-        min_temperature: 17.0,
-        max_temperature: 30.0,
+        min_temperature: *temperature_range.start() as f32,
+        max_temperature: *temperature_range.end() as f32,
         precision: 1,
-        operation_modes: Mode::iter().map(|m| m.as_ref().to_lowercase()).collect(),
-        fan_modes: Fan::iter()
-            .filter(|&m| m != Fan::Zero)
+        operation_modes: protocol
+            .modes()
+            .iter()
             .map(|m| m.as_ref().to_lowercase())
             .collect(),
-        commands,
-    };
-
-    println!("{}", serde_json::to_string_pretty(&code_file)?);
-
-    Ok(())
+        fan_modes: protocol
+            .fans()
+            .iter()
+            .map(|m| m.as_ref().to_lowercase())
+            .collect(),
+        commands: commands.into(),
+    })
 }
 
-fn encode_state(state: &ControlState) -> anyhow::Result<String> {
-    let packet: Packet = Packet::from_control_state(state)?;
-    let pulses = Phy::new().encode(packet.0)?;
+fn encode_state(protocol: &dyn Protocol, state: &ControlState) -> anyhow::Result<String> {
+    let pulses = protocol.encode(state)?;
     let recording_bytes = Recording::new_ir(pulses).to_bytes();
     Ok(base64::encode(recording_bytes))
 }
 
 #[cfg(test)]
 mod test {
-    use super::gen_smartir;
+    use super::{gen_code_file, gen_smartir};
+    use crate::lennox::Lennox;
 
     #[test]
     fn test_generate() {
-        gen_smartir().unwrap();
+        gen_smartir(&Lennox::new()).unwrap();
+    }
+
+    #[test]
+    fn test_schema() {
+        let code_file = serde_json::to_value(gen_code_file(&Lennox::new()).unwrap()).unwrap();
+
+        let mut keys: Vec<_> = code_file.as_object().unwrap().keys().cloned().collect();
+        keys.sort();
+        assert_eq!(
+            keys,
+            [
+                "commands",
+                "commandsEncoding",
+                "fanModes",
+                "manufacturer",
+                "maxTemperature",
+                "minTemperature",
+                "operationModes",
+                "precision",
+                "supportedController",
+                "supportedModels",
+            ]
+        );
+
+        let mut modes: Vec<_> = code_file["commands"].as_object().unwrap().keys().collect();
+        modes.sort();
+        assert_eq!(modes, ["auto", "cool", "dry", "fan", "heat", "off"]);
     }
 }
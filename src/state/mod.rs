@@ -0,0 +1,128 @@
+use clap::Parser;
+use serde::{Deserialize, Serialize};
+
+pub mod temperature;
+pub use temperature::{Temperature, TemperatureUnit};
+
+// The complete state sent to the heat pump
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Parser, Serialize, Deserialize)]
+pub struct ControlState {
+    /// Power state
+    #[clap(short, long)]
+    pub power: bool,
+
+    /// Operating mode
+    #[clap(short, long)]
+    pub mode: Mode,
+
+    // Current set temperature (e.g. 24, 24C or 75F), or None if it is only in fan mode
+    #[clap(short, long)]
+    pub temperature: Option<Temperature>,
+
+    // Fan speed setting
+    #[clap(short, long)]
+    pub fan: Fan,
+
+    /// Sleep mode, gradually adjusts the set temperature overnight
+    #[clap(short, long)]
+    #[serde(default)]
+    pub sleep: bool,
+
+    /// Turn the unit off after this many minutes, if the protocol supports timers
+    #[clap(long)]
+    pub off_timer: Option<u16>,
+
+    /// Turn the unit on after this many minutes, if the protocol supports timers
+    #[clap(long)]
+    pub on_timer: Option<u16>,
+}
+
+/// Changes to a ControlState, the fields that aren't given are left as they are
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Parser, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct StateUpdate {
+    /// Power state (on or off)
+    #[clap(short, long, value_parser = clap::builder::BoolishValueParser::new())]
+    pub power: Option<bool>,
+
+    /// Operating mode
+    #[clap(short, long)]
+    pub mode: Option<Mode>,
+
+    /// Set temperature (e.g. 24, 24C or 75F)
+    #[clap(short, long)]
+    pub temperature: Option<Temperature>,
+
+    /// Fan speed setting
+    #[clap(short, long)]
+    pub fan: Option<Fan>,
+
+    /// Sleep mode (on or off)
+    #[clap(short, long, value_parser = clap::builder::BoolishValueParser::new())]
+    pub sleep: Option<bool>,
+
+    /// Turn the unit off after this many minutes
+    #[clap(long)]
+    pub off_timer: Option<u16>,
+
+    /// Turn the unit on after this many minutes
+    #[clap(long)]
+    pub on_timer: Option<u16>,
+}
+
+impl StateUpdate {
+    /// Merges the update into a state. Timers are one-shot, they're only kept from the update.
+    pub fn apply(&self, state: ControlState) -> ControlState {
+        ControlState {
+            power: self.power.unwrap_or(state.power),
+            mode: self.mode.unwrap_or(state.mode),
+            temperature: self.temperature.or(state.temperature),
+            fan: self.fan.unwrap_or(state.fan),
+            sleep: self.sleep.unwrap_or(state.sleep),
+            off_timer: self.off_timer,
+            on_timer: self.on_timer,
+        }
+    }
+}
+
+#[derive(
+    Debug,
+    Clone,
+    Copy,
+    PartialEq,
+    Eq,
+    Hash,
+    Serialize,
+    Deserialize,
+    strum::EnumString,
+    strum::EnumIter,
+    strum::AsRefStr,
+)]
+pub enum Fan {
+    Min,
+    Medium,
+    Max,
+    Auto,
+    Zero,
+}
+
+#[derive(
+    Debug,
+    Clone,
+    Copy,
+    PartialEq,
+    Eq,
+    Hash,
+    Serialize,
+    Deserialize,
+    strum::EnumString,
+    strum::EnumIter,
+    strum::AsRefStr,
+)]
+pub enum Mode {
+    Auto,
+    Cool,
+    Dry,
+    Heat,
+    Fan,
+}
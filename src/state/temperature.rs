@@ -0,0 +1,173 @@
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::{fmt, ops::RangeInclusive, str::FromStr};
+use thiserror::Error;
+
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, Hash, strum::EnumString, strum::EnumIter, strum::AsRefStr,
+)]
+pub enum TemperatureUnit {
+    Celsius,
+    Fahrenheit,
+}
+
+impl TemperatureUnit {
+    /// Range of set temperatures accepted by the remote in this unit
+    pub fn range(&self) -> RangeInclusive<u8> {
+        match self {
+            TemperatureUnit::Celsius => 17..=30,
+            TemperatureUnit::Fahrenheit => 62..=86,
+        }
+    }
+
+    fn suffix(&self) -> &'static str {
+        match self {
+            TemperatureUnit::Celsius => "C",
+            TemperatureUnit::Fahrenheit => "F",
+        }
+    }
+}
+
+/// A set temperature, in the unit the remote is configured to display
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Temperature {
+    Celsius(u8),
+    Fahrenheit(u8),
+}
+
+#[derive(Error, Debug, Clone)]
+pub enum ParseTemperatureError {
+    #[error("invalid temperature: {0} (expected e.g. 24, 24C or 75F)")]
+    InvalidFormat(String),
+}
+
+impl Temperature {
+    pub fn new(value: u8, unit: TemperatureUnit) -> Self {
+        match unit {
+            TemperatureUnit::Celsius => Temperature::Celsius(value),
+            TemperatureUnit::Fahrenheit => Temperature::Fahrenheit(value),
+        }
+    }
+
+    pub fn unit(&self) -> TemperatureUnit {
+        match self {
+            Temperature::Celsius(_) => TemperatureUnit::Celsius,
+            Temperature::Fahrenheit(_) => TemperatureUnit::Fahrenheit,
+        }
+    }
+
+    pub fn value(&self) -> u8 {
+        match *self {
+            Temperature::Celsius(v) | Temperature::Fahrenheit(v) => v,
+        }
+    }
+
+    /// Whether the remote accepts this temperature as a set point
+    pub fn is_valid(&self) -> bool {
+        self.unit().range().contains(&self.value())
+    }
+}
+
+impl fmt::Display for Temperature {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}{}", self.value(), self.unit().suffix())
+    }
+}
+
+/// Parses `24`, `24C` or `75F`. Temperatures without a unit are in Celsius.
+impl FromStr for Temperature {
+    type Err = ParseTemperatureError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || ParseTemperatureError::InvalidFormat(s.into());
+
+        let trimmed = s.trim();
+        let (value, unit) = match trimmed.char_indices().last().ok_or_else(invalid)? {
+            (i, 'C' | 'c') => (&trimmed[..i], TemperatureUnit::Celsius),
+            (i, 'F' | 'f') => (&trimmed[..i], TemperatureUnit::Fahrenheit),
+            _ => (trimmed, TemperatureUnit::Celsius),
+        };
+
+        let value = value.parse().map_err(|_| invalid())?;
+        Ok(Temperature::new(value, unit))
+    }
+}
+
+/// Serialized as text, e.g. "24C" or "75F"
+impl Serialize for Temperature {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+/// Deserialized from text, or from a number in Celsius
+impl<'de> Deserialize<'de> for Temperature {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum Repr {
+            Celsius(u8),
+            Text(String),
+        }
+
+        match Repr::deserialize(deserializer)? {
+            Repr::Celsius(value) => Ok(Temperature::Celsius(value)),
+            Repr::Text(text) => text.parse().map_err(serde::de::Error::custom),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(
+            "24".parse::<Temperature>().unwrap(),
+            Temperature::Celsius(24)
+        );
+        assert_eq!(
+            "24C".parse::<Temperature>().unwrap(),
+            Temperature::Celsius(24)
+        );
+        assert_eq!(
+            "75F".parse::<Temperature>().unwrap(),
+            Temperature::Fahrenheit(75)
+        );
+        assert_eq!(
+            "75f".parse::<Temperature>().unwrap(),
+            Temperature::Fahrenheit(75)
+        );
+        assert!("F".parse::<Temperature>().is_err());
+        assert!("hot".parse::<Temperature>().is_err());
+
+        for temp in [Temperature::Celsius(17), Temperature::Fahrenheit(86)] {
+            assert_eq!(temp.to_string().parse::<Temperature>().unwrap(), temp);
+        }
+    }
+
+    #[test]
+    fn test_serde() {
+        assert_eq!(
+            serde_json::to_string(&Temperature::Fahrenheit(75)).unwrap(),
+            r#""75F""#
+        );
+        assert_eq!(
+            serde_json::from_str::<Temperature>("24").unwrap(),
+            Temperature::Celsius(24)
+        );
+        assert_eq!(
+            serde_json::from_str::<Temperature>(r#""75F""#).unwrap(),
+            Temperature::Fahrenheit(75)
+        );
+        assert!(serde_json::from_str::<Temperature>(r#""hot""#).is_err());
+    }
+
+    #[test]
+    fn test_range() {
+        assert!(Temperature::Celsius(17).is_valid());
+        assert!(!Temperature::Celsius(31).is_valid());
+        assert!(Temperature::Fahrenheit(62).is_valid());
+        assert!(!Temperature::Fahrenheit(30).is_valid());
+    }
+}
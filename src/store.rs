@@ -0,0 +1,312 @@
+/** Assumed state of each unit, persisted to a JSON file shared by every command
+ * IR is one-way and state packets always carry the complete state, so partial updates are merged
+ * into the last state sent. Updates hold an advisory lock on a file next to the state file while
+ * they read, send and write the state, so that two tools updating the same file never undo each
+ * other's changes. The lock is released by the OS when a tool exits without releasing it.
+ */
+use std::{
+    collections::BTreeMap,
+    fs::{self, File, OpenOptions},
+    io,
+    path::{Path, PathBuf},
+    thread,
+    time::{Duration, Instant},
+};
+
+use fs2::FileExt;
+use thiserror::Error;
+
+use crate::state::ControlState;
+
+#[derive(Error, Debug)]
+pub enum StoreError {
+    #[error("I/O error on {0}: {1}")]
+    Io(PathBuf, #[source] io::Error),
+    #[error("invalid state file {0}: {1}")]
+    Json(PathBuf, #[source] serde_json::Error),
+    #[error("timed out waiting for the lock on {0}")]
+    Locked(PathBuf),
+}
+
+/// Unit name -> last state sent
+pub type States = BTreeMap<String, ControlState>;
+
+pub struct Store {
+    path: PathBuf,
+}
+
+impl Store {
+    /// Time to wait for another tool to release the lock
+    const LOCK_TIMEOUT: Duration = Duration::from_secs(10);
+
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self { path: path.into() }
+    }
+
+    /// $XDG_STATE_HOME/heatpump/states.json, or ~/.local/state/heatpump/states.json
+    pub fn default_path() -> PathBuf {
+        let state_home = std::env::var_os("XDG_STATE_HOME")
+            .map(PathBuf::from)
+            .or_else(|| {
+                std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".local/state"))
+            })
+            .unwrap_or_default();
+
+        state_home.join("heatpump").join("states.json")
+    }
+
+    /// Every stored state
+    pub fn states(&self) -> Result<States, StoreError> {
+        match fs::read(&self.path) {
+            Ok(contents) => serde_json::from_slice(&contents)
+                .map_err(|e| StoreError::Json(self.path.clone(), e)),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(States::new()),
+            Err(e) => Err(StoreError::Io(self.path.clone(), e)),
+        }
+    }
+
+    /// The last state sent to a unit, or the given initial state if nothing was sent yet
+    pub fn state(&self, unit: &str, initial: ControlState) -> Result<ControlState, StoreError> {
+        Ok(self.states()?.get(unit).copied().unwrap_or(initial))
+    }
+
+    /// Replaces the state of a unit with the one returned by `update`, given the current one.
+    /// The file stays locked until `update` returns, and is left untouched when it fails, so it
+    /// can send the new state first.
+    pub fn update<F, E>(
+        &self,
+        unit: &str,
+        initial: ControlState,
+        update: F,
+    ) -> Result<ControlState, E>
+    where
+        F: FnOnce(ControlState) -> Result<ControlState, E>,
+        E: From<StoreError>,
+    {
+        let _lock = Lock::acquire(&self.path)?;
+
+        let mut states = self.states()?;
+        let state = update(states.get(unit).copied().unwrap_or(initial))?;
+
+        // Timers were sent along with the state, they don't apply to the next updates
+        let stored = ControlState {
+            off_timer: None,
+            on_timer: None,
+            ..state
+        };
+        states.insert(unit.into(), stored);
+        self.write(&states)?;
+
+        Ok(state)
+    }
+
+    /// Replaces the file, through a temporary file so that readers never see a partial write
+    fn write(&self, states: &States) -> Result<(), StoreError> {
+        let io_error = |e| StoreError::Io(self.path.clone(), e);
+
+        if let Some(dir) = self.path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+            fs::create_dir_all(dir).map_err(io_error)?;
+        }
+
+        let temporary = with_suffix(&self.path, ".tmp");
+        let contents = serde_json::to_vec_pretty(states).unwrap();
+        fs::write(&temporary, contents).map_err(io_error)?;
+        fs::rename(&temporary, &self.path).map_err(io_error)
+    }
+}
+
+fn with_suffix(path: &Path, suffix: &str) -> PathBuf {
+    let mut path = path.as_os_str().to_owned();
+    path.push(suffix);
+    path.into()
+}
+
+/// Exclusive advisory lock on a file next to the state file, released when dropped. The file
+/// itself is left in place: removing it would let a waiter lock a file that is no longer there.
+struct Lock {
+    file: File,
+}
+
+impl Lock {
+    fn acquire(store: &Path) -> Result<Self, StoreError> {
+        let path = with_suffix(store, ".lock");
+        let io_error = |e| StoreError::Io(path.clone(), e);
+        if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+            fs::create_dir_all(dir).map_err(io_error)?;
+        }
+
+        let file = OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(&path)
+            .map_err(io_error)?;
+
+        let start = Instant::now();
+        loop {
+            match file.try_lock_exclusive() {
+                Ok(()) => return Ok(Self { file }),
+                Err(e) if e.kind() == fs2::lock_contended_error().kind() => {}
+                Err(e) => return Err(io_error(e)),
+            }
+
+            if start.elapsed() > Store::LOCK_TIMEOUT {
+                return Err(StoreError::Locked(path));
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+    }
+}
+
+impl Drop for Lock {
+    fn drop(&mut self) {
+        let _ = self.file.unlock();
+    }
+}
+
+/// Store in a temporary directory of its own, removed when dropped
+#[cfg(test)]
+pub struct TempStore {
+    store: Store,
+    dir: PathBuf,
+}
+
+#[cfg(test)]
+impl TempStore {
+    pub fn new(name: &str) -> Self {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+        static COUNT: AtomicUsize = AtomicUsize::new(0);
+
+        let dir = std::env::temp_dir().join(format!(
+            "heatpump-{}-{}-{}",
+            name,
+            std::process::id(),
+            COUNT.fetch_add(1, Ordering::Relaxed)
+        ));
+        let _ = fs::remove_dir_all(&dir);
+        Self {
+            store: Store::new(dir.join("states.json")),
+            dir,
+        }
+    }
+
+    /// Another store on the same file, e.g. for a thread outliving this one
+    pub fn open(&self) -> Store {
+        Store::new(&self.store.path)
+    }
+}
+
+#[cfg(test)]
+impl std::ops::Deref for TempStore {
+    type Target = Store;
+
+    fn deref(&self) -> &Store {
+        &self.store
+    }
+}
+
+#[cfg(test)]
+impl Drop for TempStore {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.dir);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::{
+        lennox::Lennox,
+        protocol,
+        state::{Fan, Mode, StateUpdate, Temperature},
+    };
+
+    #[test]
+    fn test_update() {
+        let store = TempStore::new("update");
+        let initial = protocol::initial_state(&Lennox::new());
+        assert_eq!(store.state("bedroom", initial).unwrap(), initial);
+
+        let heat = StateUpdate {
+            power: Some(true),
+            mode: Some(Mode::Heat),
+            off_timer: Some(60),
+            ..Default::default()
+        };
+        let state = store
+            .update("bedroom", initial, |state| {
+                Ok::<_, StoreError>(heat.apply(state))
+            })
+            .unwrap();
+        assert_eq!(state.off_timer, Some(60));
+
+        // Partial updates keep the other settings, but not the timers
+        let fan = StateUpdate {
+            fan: Some(Fan::Max),
+            ..Default::default()
+        };
+        let state = store
+            .update("bedroom", initial, |state| {
+                Ok::<_, StoreError>(fan.apply(state))
+            })
+            .unwrap();
+        assert_eq!(
+            state,
+            ControlState {
+                power: true,
+                mode: Mode::Heat,
+                fan: Fan::Max,
+                off_timer: None,
+                ..initial
+            }
+        );
+
+        // Failed updates leave the state as it was
+        let failed = store.update("bedroom", initial, |_| Err(anyhow::anyhow!("not sent")));
+        assert!(failed.is_err());
+        assert_eq!(store.state("bedroom", initial).unwrap(), state);
+        assert_eq!(store.states().unwrap().len(), 1);
+        assert!(Lock::acquire(&store.path).is_ok());
+    }
+
+    #[test]
+    fn test_concurrent_updates() {
+        let store = Arc::new(TempStore::new("concurrent"));
+        let initial = protocol::initial_state(&Lennox::new());
+
+        // One tool changes the temperature while another changes the fan, neither is lost
+        let updates = [
+            StateUpdate {
+                temperature: Some(Temperature::Celsius(26)),
+                ..Default::default()
+            },
+            StateUpdate {
+                fan: Some(Fan::Min),
+                ..Default::default()
+            },
+        ];
+        let threads: Vec<_> = updates
+            .into_iter()
+            .map(|update| {
+                let store = store.clone();
+                thread::spawn(move || {
+                    store
+                        .update("living", initial, |state| {
+                            thread::sleep(Duration::from_millis(50));
+                            Ok::<_, StoreError>(update.apply(state))
+                        })
+                        .unwrap();
+                })
+            })
+            .collect();
+        for thread in threads {
+            thread.join().unwrap();
+        }
+
+        let state = store.state("living", initial).unwrap();
+        assert_eq!(state.temperature, Some(Temperature::Celsius(26)));
+        assert_eq!(state.fan, Fan::Min);
+    }
+}
@@ -0,0 +1,105 @@
+/** Backends sending codes to the heat pump, shared by the long-running commands (mqtt, serve)
+ * Codes go through a Broadlink device, or any closure, e.g. one printing them.
+ */
+use std::time::Duration;
+
+use crate::broadlink::{
+    client::{Client, ClientError, DeviceOpts},
+    Recording,
+};
+
+/// Sends the codes encoding states to the heat pump
+pub trait Transmitter {
+    /// Transmits pulses modulated at the given carrier frequency (in Hz)
+    fn transmit(&mut self, frequency: u32, pulses: Vec<Duration>) -> anyhow::Result<()>;
+}
+
+impl Transmitter for Client {
+    fn transmit(&mut self, _frequency: u32, pulses: Vec<Duration>) -> anyhow::Result<()> {
+        Ok(self.send(&Recording::new_ir(pulses))?)
+    }
+}
+
+/// Broadlink device found and authenticated again when it stops answering, or rejects the
+/// session after rebooting
+pub struct Reconnecting {
+    opts: DeviceOpts,
+    client: Option<Client>,
+}
+
+impl Reconnecting {
+    pub fn connect(opts: DeviceOpts) -> Result<Self, ClientError> {
+        let client = Client::connect(opts.device()?, opts.timeout())?;
+        Ok(Self {
+            opts,
+            client: Some(client),
+        })
+    }
+
+    pub fn client(&self) -> Option<&Client> {
+        self.client.as_ref()
+    }
+
+    fn send(&mut self, recording: &Recording) -> Result<(), ClientError> {
+        let client = match &mut self.client {
+            Some(client) => client,
+            None => self
+                .client
+                .insert(Client::connect(self.opts.device()?, self.opts.timeout())?),
+        };
+
+        client.send(recording).inspect_err(|e| {
+            if matches!(e, ClientError::Timeout | ClientError::Device(_)) {
+                self.client = None;
+            }
+        })
+    }
+}
+
+impl Transmitter for Reconnecting {
+    fn transmit(&mut self, _frequency: u32, pulses: Vec<Duration>) -> anyhow::Result<()> {
+        let recording = Recording::new_ir(pulses);
+        match self.send(&recording) {
+            // Retried once on a new session
+            Err(ClientError::Timeout | ClientError::Device(_)) if self.client.is_none() => {
+                Ok(self.send(&recording)?)
+            }
+            result => Ok(result?),
+        }
+    }
+}
+
+impl<F> Transmitter for F
+where
+    F: FnMut(u32, Vec<Duration>) -> anyhow::Result<()>,
+{
+    fn transmit(&mut self, frequency: u32, pulses: Vec<Duration>) -> anyhow::Result<()> {
+        self(frequency, pulses)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::broadlink::mock::MockDevice;
+
+    #[test]
+    fn test_reconnect() {
+        let mock = MockDevice::spawn(0x5216).unwrap();
+        let opts = DeviceOpts {
+            host: Some(mock.addr().to_string()),
+            mac: None,
+            device_config: None,
+            timeout: 1,
+        };
+        let mut transmitter = Reconnecting::connect(opts).unwrap();
+
+        let pulses = vec![Duration::from_micros(500); 4];
+        transmitter.transmit(38000, pulses.clone()).unwrap();
+
+        // The session is lost when the device reboots, a new one is opened
+        mock.reboot();
+        transmitter.transmit(38000, pulses.clone()).unwrap();
+        assert_eq!(mock.received().len(), 2);
+    }
+}
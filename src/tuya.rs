@@ -0,0 +1,220 @@
+/** Encoding/decoding of Tuya IR blaster codes (ir_code), as used by Tuya and Moes IR blasters
+ * Codes are little-endian u16 pulse lengths (in microseconds), compressed with a FastLZ-style
+ * scheme and base64-encoded.
+ * Compression scheme from: https://gist.github.com/mildsunrise/1d576669b63a260d2cff35fda63ec0b5
+ */
+use std::time::Duration;
+
+use bytes::{Buf, BufMut, BytesMut};
+use thiserror::Error;
+
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum TuyaError {
+    #[error("invalid base64: {0}")]
+    Base64(#[from] base64::DecodeError),
+    #[error("truncated block")]
+    TruncatedBlock,
+    #[error("back-reference distance {0} is beyond the start of the data")]
+    InvalidDistance(usize),
+    #[error("odd number of bytes, pulses are 16 bits long")]
+    OddLength,
+}
+
+/// Longest literal block
+const MAX_LITERAL: usize = 32;
+/// Back-references shorter than this take more room than the literal bytes
+const MIN_MATCH: usize = 3;
+/// Longest back-reference: 7 in the header, plus an extra byte, plus the implicit 2
+const MAX_MATCH: usize = 7 + 255 + 2;
+/// Back-reference distances are 13 bits long
+const MAX_DISTANCE: usize = 1 << 13;
+
+/// Encodes on-off pulse durations into a Tuya code
+pub fn encode(pulses: &[Duration]) -> String {
+    let mut raw = BytesMut::with_capacity(pulses.len() * 2);
+    for pulse in pulses {
+        raw.put_u16_le(pulse.as_micros().min(u16::MAX as _) as u16);
+    }
+
+    base64::encode(compress(&raw))
+}
+
+/// Decodes a Tuya code into on-off pulse durations
+pub fn decode(code: &str) -> Result<Vec<Duration>, TuyaError> {
+    let raw = decompress(&base64::decode(code.trim())?)?;
+    if raw.len() % 2 != 0 {
+        return Err(TuyaError::OddLength);
+    }
+
+    let mut raw = &raw[..];
+    let mut pulses = Vec::with_capacity(raw.len() / 2);
+    while raw.has_remaining() {
+        pulses.push(Duration::from_micros(raw.get_u16_le() as _));
+    }
+
+    Ok(pulses)
+}
+
+/// Blocks start with a header byte holding a 3 bit length and a 5 bit value. A zero length
+/// introduces a literal block of value + 1 bytes. Otherwise, the block copies length + 2 bytes
+/// from earlier output, at a distance held in the value and the next byte. A length of 7 is
+/// extended by another byte.
+fn decompress(mut input: &[u8]) -> Result<Vec<u8>, TuyaError> {
+    let mut out = Vec::new();
+
+    while input.has_remaining() {
+        let header = input.get_u8();
+        let (length, value) = ((header >> 5) as usize, (header & 0b11111) as usize);
+
+        if length == 0 {
+            let length = value + 1;
+            if input.remaining() < length {
+                return Err(TuyaError::TruncatedBlock);
+            }
+            out.extend_from_slice(&input[..length]);
+            input.advance(length);
+            continue;
+        }
+
+        let extended = length == 7;
+        if input.remaining() < 1 + extended as usize {
+            return Err(TuyaError::TruncatedBlock);
+        }
+        let length = match extended {
+            true => length + input.get_u8() as usize,
+            false => length,
+        } + 2;
+        let distance = (value << 8 | input.get_u8() as usize) + 1;
+
+        let start = out
+            .len()
+            .checked_sub(distance)
+            .ok_or(TuyaError::InvalidDistance(distance))?;
+
+        // The copied range may overlap with the bytes being written
+        for i in start..start + length {
+            out.push(out[i]);
+        }
+    }
+
+    Ok(out)
+}
+
+/// Greedy compression, emitting the longest back-reference found at each position
+fn compress(input: &[u8]) -> Vec<u8> {
+    let mut out = Vec::new();
+    let mut literal_start = 0;
+    let mut pos = 0;
+
+    let flush_literals = |out: &mut Vec<u8>, literals: &[u8]| {
+        for chunk in literals.chunks(MAX_LITERAL) {
+            out.push((chunk.len() - 1) as u8);
+            out.extend_from_slice(chunk);
+        }
+    };
+
+    while pos < input.len() {
+        let (length, distance) = longest_match(input, pos);
+        if length < MIN_MATCH {
+            pos += 1;
+            continue;
+        }
+
+        flush_literals(&mut out, &input[literal_start..pos]);
+
+        let (length_code, distance_code) = (length - 2, distance - 1);
+        let high = (distance_code >> 8) as u8;
+        if length_code < 7 {
+            out.push((length_code as u8) << 5 | high);
+        } else {
+            out.push(7 << 5 | high);
+            out.push((length_code - 7) as u8);
+        }
+        out.push(distance_code as u8);
+
+        pos += length;
+        literal_start = pos;
+    }
+
+    flush_literals(&mut out, &input[literal_start..]);
+    out
+}
+
+/// Longest run of bytes at `pos` that also starts within the window before it, as
+/// (length, distance)
+fn longest_match(input: &[u8], pos: usize) -> (usize, usize) {
+    let mut best = (0, 0);
+    let max_length = MAX_MATCH.min(input.len() - pos);
+
+    for start in pos.saturating_sub(MAX_DISTANCE)..pos {
+        let length = (0..max_length)
+            .take_while(|&i| input[start + i] == input[pos + i])
+            .count();
+        if length > best.0 {
+            best = (length, pos - start);
+        }
+    }
+
+    best
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        lennox::Lennox,
+        protocol::Protocol,
+        state::{ControlState, Fan, Mode, Temperature},
+    };
+
+    #[test]
+    fn test_decompress() {
+        // Literal "ab", then 3 bytes from 2 bytes back, then 10 bytes from 1 byte back
+        let compressed = [0x01, b'a', b'b', 0x20, 0x01, 0xe0, 0x01, 0x00];
+        let expected = [b"ababa".as_slice(), &[b'a'; 10]].concat();
+        assert_eq!(decompress(&compressed).unwrap(), expected);
+
+        assert_eq!(decompress(&[0x05, b'a']), Err(TuyaError::TruncatedBlock));
+        assert_eq!(
+            decompress(&[0x00, b'a', 0x20, 0x04]),
+            Err(TuyaError::InvalidDistance(5))
+        );
+    }
+
+    #[test]
+    fn test_compress() {
+        let inputs: [&[u8]; 4] = [b"", b"abc", b"ababaaaaaaaaaa", &[0x42; 1000]];
+        for input in inputs {
+            assert_eq!(decompress(&compress(input)).unwrap(), input);
+        }
+
+        // Long literal runs are split into blocks
+        let literals: Vec<u8> = (0..100).collect();
+        assert_eq!(decompress(&compress(&literals)).unwrap(), literals);
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let lennox = Lennox::new();
+        let state = ControlState {
+            power: true,
+            mode: Mode::Heat,
+            temperature: Some(Temperature::Celsius(24)),
+            fan: Fan::Auto,
+            sleep: false,
+            off_timer: None,
+            on_timer: None,
+        };
+        let pulses = lennox.encode(&state).unwrap();
+        let code = encode(&pulses);
+
+        // The frames repeat the same few pulse lengths, they compress well
+        assert!(base64::decode(&code).unwrap().len() < pulses.len());
+
+        assert_eq!(decode(&code).unwrap(), pulses);
+        assert_eq!(
+            lennox.decode(&decode(&code).unwrap()).unwrap().mode,
+            state.mode
+        );
+    }
+}